@@ -6,7 +6,7 @@ use serde::Deserialize;
 
 use futures::prelude::*;
 use gotham::handler::*;
-use gotham::helpers::http::response::create_empty_response;
+use gotham::helpers::http::response::{create_empty_response, create_response};
 use gotham::hyper::{self, body::Bytes, Body, Response, StatusCode};
 use gotham::middleware::logger::RequestLogger;
 use gotham::middleware::{state::StateMiddleware, Middleware};
@@ -14,72 +14,589 @@ use gotham::pipeline::{new_pipeline, single::single_pipeline};
 use gotham::router::{builder::*, Router};
 use gotham::state::{FromState, State};
 use gotham_derive::*;
+use image::error::ImageFormatHint;
 use std::pin::Pin;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
-use crate::display::{image::*, power::*, result::*};
+use crate::backend::*;
+use crate::backlight::*;
+use crate::display::{
+    background::*, backlight::*, cec::*, image::*, orientation::*, overlay::*, playlist::*,
+    power::*, rect::*, region::*, result::*, transition::*,
+};
 use crate::error::*;
-use crate::pipeline::*;
+use crate::fetch::*;
+use crate::regions::*;
+use crate::schedule::*;
+use crate::slideshow::*;
+use crate::store::*;
 
 #[derive(Deserialize, StateData, StaticResponseExtender)]
 struct ImageDisplayOption {
     format: Option<String>,
     mode: Option<String>,
+    transition: Option<String>,
+    duration: Option<u32>,
+    rotate: Option<u32>,
+    flip: Option<String>,
+    background: Option<String>,
+    focus_x: Option<f32>,
+    focus_y: Option<f32>,
+    crop: Option<String>,
+    title: Option<String>,
 }
 
-fn load_image(body: Bytes, format: Option<&str>) -> Result<DisplayImage, ImageError> {
+#[derive(Deserialize)]
+struct ImageUrlOption {
+    url: String,
+    #[serde(flatten)]
+    option: ImageDisplayOption,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct RegionPath {
+    name: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct RegionOption {
+    x: i16,
+    y: i16,
+    w: i16,
+    h: i16,
+    layer: Option<i32>,
+    opacity: Option<f32>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct BrightnessOption {
+    level: u32,
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct OverlayPath {
+    name: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct OverlayOption {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+    format: Option<String>,
+    anchor: Option<String>,
+    size: Option<u32>,
+    color: Option<String>,
+    background: Option<String>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SlidePath {
+    id: u32,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SlideMoveOption {
+    index: usize,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SlideshowIntervalOption {
+    seconds: u64,
+}
+
+const INDEX_HTML: &str = include_str!("../assets/index.html");
+
+// Browsers play GIF frames with a shorter delay than this at 100ms
+const MIN_FRAME_DELAY: u32 = 20;
+const DEFAULT_FRAME_DELAY: u32 = 100;
+const MAX_ANIMATION_FRAMES: usize = 1000;
+// 256 MiB of RGBA frames after downscaling
+const MAX_ANIMATION_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// Returns the smallest size that still covers `max_size` in either orientation.
+fn downscale_size(size: (u32, u32), max_size: (u32, u32)) -> (u32, u32) {
+    let ((w, h), (mw, mh)) = (size, max_size);
+    if w == 0 || h == 0 || mw == 0 || mh == 0 {
+        return size;
+    }
+    let cover = |bw: u32, bh: u32| (bw as f32 / w as f32).max(bh as f32 / h as f32);
+    let scale = cover(mw, mh).max(cover(mh, mw));
+    if scale >= 1f32 {
+        return size;
+    }
+    (
+        ((w as f32 * scale).round() as u32).max(1),
+        ((h as f32 * scale).round() as u32).max(1),
+    )
+}
+
+fn downscale(image: image::RgbaImage, target: (u32, u32)) -> image::RgbaImage {
+    use image::imageops::{self, FilterType};
+
+    match image.dimensions() == target {
+        true => image,
+        false => imageops::resize(&image, target.0, target.1, FilterType::CatmullRom),
+    }
+}
+
+fn decode_image(
+    image: image::io::Reader<std::io::Cursor<Bytes>>,
+    format: image::ImageFormat,
+    size: usize,
+    max_size: (u32, u32),
+) -> image::ImageResult<DisplayImage> {
+    use image::codecs::{gif::GifDecoder, jpeg::JpegDecoder, webp::WebPDecoder};
+    use image::error::{LimitError, LimitErrorKind, ParameterError, ParameterErrorKind};
+    use image::io::Limits;
+    use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat};
+
+    let still = |image: image::RgbaImage, original: (u32, u32)| {
+        let target = downscale_size(original, max_size);
+        let mut image = DisplayImage::new(downscale(image, target), size, format);
+        image.set_scale(target.0 as f32 / original.0 as f32);
+        image
+    };
+
+    let frames = match format {
+        ImageFormat::Gif => {
+            let mut decoder = GifDecoder::new(image.into_inner())?;
+            decoder.set_limits(Limits::default())?;
+            decoder.into_frames()
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(image.into_inner())?;
+            match decoder.has_animation() {
+                true => decoder.into_frames(),
+                false => {
+                    let image = DynamicImage::from_decoder(decoder)?.to_rgba8();
+                    let original = image.dimensions();
+                    return Ok(still(image, original));
+                }
+            }
+        }
+        ImageFormat::Jpeg => {
+            // Let the decoder skip DCT coefficients instead of decoding at full size
+            let mut decoder = JpegDecoder::new(image.into_inner())?;
+            let original = decoder.dimensions();
+            let (w, h) = downscale_size(original, max_size);
+            if (w, h) != original {
+                decoder.scale(w as u16, h as u16)?;
+            }
+            let image = DynamicImage::from_decoder(decoder)?.to_rgba8();
+            return Ok(still(image, original));
+        }
+        _ => {
+            let image = image.decode()?.to_rgba8();
+            let original = image.dimensions();
+            return Ok(still(image, original));
+        }
+    };
+
+    // Downscale every frame as it is decoded, so only one full size frame is held at a time
+    let mut original = None;
+    let (mut count, mut pixels) = (0usize, 0u64);
+    let mut first = None;
+    let mut decoded = Vec::new();
+    for frame in frames {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = match numer / denom.max(1) {
+            delay if delay < MIN_FRAME_DELAY => DEFAULT_FRAME_DELAY,
+            delay => delay,
+        };
+        let delay = Duration::from_millis(delay as u64);
+        let image = frame.into_buffer();
+        let original = *original.get_or_insert(image.dimensions());
+        let target = downscale_size(original, max_size);
+
+        count += 1;
+        pixels += target.0 as u64 * target.1 as u64;
+        if count > MAX_ANIMATION_FRAMES || pixels > MAX_ANIMATION_PIXELS {
+            return Err(image::ImageError::Limits(LimitError::from_kind(
+                LimitErrorKind::InsufficientMemory,
+            )));
+        }
+        if count == 1 {
+            // A single frame is shown as a still image without downscaling twice
+            first = Some((image, delay));
+            continue;
+        }
+        if let Some((image, delay)) = first.take() {
+            decoded.push((downscale(image, target), delay));
+        }
+        decoded.push((downscale(image, target), delay));
+    }
+
+    let original = match original {
+        Some(original) => original,
+        None => {
+            return Err(image::ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::NoMoreData,
+            )))
+        }
+    };
+    if let Some((image, _)) = first {
+        return Ok(still(image, original));
+    }
+    let target = downscale_size(original, max_size);
+    let mut image = DisplayImage::new_animated(decoded, size, format);
+    image.set_scale(target.0 as f32 / original.0 as f32);
+    Ok(image)
+}
+
+fn unsupported_format(format: ImageFormatHint) -> ImageError {
+    ImageError {
+        image_error: image::ImageError::Unsupported(format.into()),
+    }
+}
+
+fn is_heif(format: Option<&str>, body: &[u8]) -> bool {
+    match format {
+        Some("image/heic") | Some("image/heif") | Some("heic") | Some("heif") => true,
+        _ => {
+            body.len() >= 12
+                && &body[4..8] == b"ftyp"
+                && [
+                    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+                ]
+                .iter()
+                .any(|brand| &body[8..12] == *brand)
+        }
+    }
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(body: &[u8], size: usize, max_size: (u32, u32)) -> image::ImageResult<DisplayImage> {
+    use image::error::DecodingError;
+    use libheif_rs::{ColorSpace, HeifContext, HeifError, LibHeif, RgbChroma};
+
+    let decoding_error = |err: HeifError| {
+        image::ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Name("heif".into()),
+            err,
+        ))
+    };
+    let ctx = HeifContext::read_from_bytes(body).map_err(decoding_error)?;
+    let handle = ctx.primary_image_handle().map_err(decoding_error)?;
+    // libheif applies the rotation and mirroring stored in the container
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(decoding_error)?;
+    let original = (image.width(), image.height());
+    let target = downscale_size(original, max_size);
+    let image = match target == original {
+        true => image,
+        false => image
+            .scale(target.0, target.1, None)
+            .map_err(decoding_error)?,
+    };
+
+    let planes = image.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| unsupported_format(ImageFormatHint::Name("heif".into())).image_error)?;
+    let row = plane.width as usize * 4;
+    let mut buffer = Vec::with_capacity(row * plane.height as usize);
+    for line in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        buffer.extend_from_slice(&line[..row]);
+    }
+    let image = image::RgbaImage::from_raw(plane.width, plane.height, buffer)
+        .ok_or_else(|| unsupported_format(ImageFormatHint::Name("heif".into())).image_error)?;
+    let mut image = DisplayImage::new(image, size, SourceFormat::Heif);
+    image.set_scale(target.0 as f32 / original.0 as f32);
+    Ok(image)
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(
+    _body: &[u8],
+    _size: usize,
+    _max_size: (u32, u32),
+) -> image::ImageResult<DisplayImage> {
+    Err(unsupported_format(ImageFormatHint::Name("heif".into())).image_error)
+}
+
+pub fn load_image(
+    body: Bytes,
+    format: Option<&str>,
+    max_size: (u32, u32),
+) -> Result<DisplayImage, ImageError> {
     use image::io::Reader as ImageReader;
-    use image::ImageFormat::{Bmp, Jpeg, Png};
+    use image::ImageFormat;
 
     let size = body.len();
+    if is_heif(format, &body) {
+        return decode_heif(&body, size, max_size)
+            .map_err(|image_error| ImageError { image_error });
+    }
+
+    let orientation = Orientation::from_exif(&body);
+    let caption = Caption::from_exif(&body);
     let cur = std::io::Cursor::new(body);
     let mut image = ImageReader::new(cur);
-    match format {
-        Some("image/png") | Some("png") => image.set_format(Png),
-        Some("image/jpeg") | Some("jpeg") | Some("jpg") => image.set_format(Jpeg),
-        Some("image/bmp") | Some("bmp") => image.set_format(Bmp),
-        _ => image = image.with_guessed_format().unwrap(),
+    match format
+        .and_then(|f| ImageFormat::from_mime_type(f).or_else(|| ImageFormat::from_extension(f)))
+    {
+        Some(format) => image.set_format(format),
+        None => {
+            image = image.with_guessed_format().map_err(|e| ImageError {
+                image_error: e.into(),
+            })?
+        }
     };
 
-    let format = image.format().unwrap();
-    let image = match image.decode() {
+    let format = match image.format() {
+        Some(format) => format,
+        None => return Err(unsupported_format(ImageFormatHint::Unknown)),
+    };
+    let mut image = match decode_image(image, format, size, max_size) {
         Ok(image) => image,
         Err(image_error) => return Err(ImageError { image_error }),
     };
-    let image = image::DynamicImage::to_rgba8(&image);
-    Ok(DisplayImage::new(image, size, format))
+    image.set_orientation(orientation);
+    image.set_caption(caption);
+    Ok(image)
 }
 
-async fn show_image(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
-    let body = Body::take_from(state);
-    let query = ImageDisplayOption::take_from(state);
-    let headers = hyper::HeaderMap::borrow_from(state);
+fn request_layout(query: &ImageDisplayOption) -> Result<Layout, ImageError> {
+    let orientation =
+        Orientation::from_str(query.rotate, query.flip.as_deref()).ok_or_else(|| {
+            ImageError::invalid_option(
+                "rotate must be 0, 90, 180 or 270, and flip h or v".to_string(),
+            )
+        })?;
+    Ok(Layout {
+        orientation,
+        background: Background::from_str(query.background.as_deref().unwrap_or_default()),
+        focus: Focus::new(query.focus_x, query.focus_y),
+        crop: query.crop.as_deref().and_then(CropRect::from_str),
+    })
+}
 
-    let whole_body = hyper::body::to_bytes(body).await?;
-    let format = query.format.or(headers
+fn request_format(state: &State, format: Option<String>) -> Option<String> {
+    let headers = hyper::HeaderMap::borrow_from(state);
+    format.or(headers
         .get(hyper::header::CONTENT_TYPE)
-        .and_then(|f| f.to_str().ok().and_then(|s| Some(String::from(s)))));
+        .and_then(|f| f.to_str().ok().map(String::from)))
+}
+
+/// Decodes and shows the image on a blocking thread, as transitions sleep between frames.
+fn display_image(
+    state: &State,
+    whole_body: Bytes,
+    format: Option<String>,
+    query: ImageDisplayOption,
+) -> JoinHandle<DisplayResult> {
+    let backend = Backend::borrow_from(state).clone();
+    let store = ImageStore::borrow_from(state).clone();
+    tokio::task::spawn_blocking(move || load_and_show(&backend, &store, whole_body, format, query))
+}
+
+fn load_and_show(
+    backend: &Backend,
+    store: &ImageStore,
+    whole_body: Bytes,
+    format: Option<String>,
+    query: ImageDisplayOption,
+) -> DisplayResult {
+    let transition = Transition::from_str(
+        query.transition.as_deref().unwrap_or_default(),
+        query.duration,
+    );
+    if !backend.supports_transition(transition) {
+        let err = transition.unsupported_error();
+        return DisplayResult {
+            status: err.status(),
+            error: Some(err),
+            ..Default::default()
+        };
+    }
+    let layout = match request_layout(&query) {
+        Ok(layout) => layout,
+        Err(err) => {
+            return DisplayResult {
+                status: err.status(),
+                error: Some(err),
+                ..Default::default()
+            }
+        }
+    };
 
-    let image = match load_image(whole_body, format.as_deref()) {
+    let mut image = match load_image(
+        whole_body.clone(),
+        format.as_deref(),
+        backend.max_image_size(),
+    ) {
         Ok(image) => image,
         Err(err) => {
-            return Ok(DisplayResult {
-                status: StatusCode::BAD_REQUEST,
+            return DisplayResult {
+                status: err.status(),
                 error: Some(err),
                 ..Default::default()
-            })
+            }
         }
     };
 
     let content_mode = ContentMode::from_str(query.mode.as_deref().unwrap_or_default());
+    layout.apply(&mut image);
+    image.set_title(query.title);
 
-    let pipeline = Pipeline::borrow_mut_from(state);
-    pipeline.render_image(&image, content_mode, 2000).unwrap();
+    if let Err(err) = backend.render(&image, content_mode, transition, RENDER_TIMEOUT) {
+        log::error!("Failed to render image: {}", err);
+        return DisplayResult {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            ..Default::default()
+        };
+    }
+
+    if let Err(err) = store.save(&whole_body, format.as_deref(), &image, content_mode) {
+        log::warn!("Failed to save displayed image: {:?}", err);
+    }
 
-    Ok(DisplayResult {
-        image: Some(image),
+    DisplayResult {
+        image: Some(image.info()),
         content_mode: Some(content_mode),
+        transition: Some(transition),
         ..Default::default()
-    })
+    }
+}
+
+fn multipart_boundary(state: &State) -> Option<String> {
+    let headers = hyper::HeaderMap::borrow_from(state);
+    headers
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|f| f.to_str().ok())
+        .and_then(|s| multer::parse_boundary(s).ok())
+}
+
+/// Reads the image part of a form, letting the other fields override `query`.
+async fn read_multipart(
+    body: Body,
+    boundary: String,
+    query: &mut ImageDisplayOption,
+) -> Result<Option<(Bytes, Option<String>)>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut multipart = multer::Multipart::new(body, boundary);
+    let mut image = None;
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().map(String::from);
+        if name.as_deref() == Some("image") || field.file_name().is_some() {
+            let content_type = field.content_type().map(|m| String::from(m.essence_str()));
+            image = Some((field.bytes().await?, content_type));
+            continue;
+        }
+
+        let value = field.text().await?;
+        if value.is_empty() {
+            continue;
+        }
+        match name.as_deref() {
+            Some("format") => query.format = Some(value),
+            Some("mode") => query.mode = Some(value),
+            Some("transition") => query.transition = Some(value),
+            Some("duration") => query.duration = value.parse().ok(),
+            Some("rotate") => query.rotate = Some(value.parse()?),
+            Some("flip") => query.flip = Some(value),
+            Some("background") => query.background = Some(value),
+            Some("focus_x") => query.focus_x = value.parse().ok(),
+            Some("focus_y") => query.focus_y = value.parse().ok(),
+            Some("crop") => query.crop = Some(value),
+            Some("title") => query.title = Some(value),
+            _ => {}
+        }
+    }
+
+    Ok(image)
+}
+
+async fn show_image(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let body = Body::take_from(state);
+    let mut query = ImageDisplayOption::take_from(state);
+
+    let (whole_body, format) = match multipart_boundary(state) {
+        Some(boundary) => match read_multipart(body, boundary, &mut query).await {
+            Ok(Some((image, content_type))) => (image, query.format.take().or(content_type)),
+            Ok(None) | Err(_) => {
+                return Ok(DisplayResult {
+                    status: StatusCode::BAD_REQUEST,
+                    ..Default::default()
+                })
+            }
+        },
+        None => {
+            let whole_body = hyper::body::to_bytes(body).await?;
+            (whole_body, request_format(state, query.format.take()))
+        }
+    };
+
+    Ok(display_image(state, whole_body, format, query).await?)
+}
+
+async fn show_url(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let body = Body::take_from(state);
+
+    let whole_body = hyper::body::to_bytes(body).await?;
+    let mut request: ImageUrlOption = match serde_json::from_slice(&whole_body) {
+        Ok(request) => request,
+        Err(_) => {
+            return Ok(DisplayResult {
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            })
+        }
+    };
+
+    let (image_body, content_type) = match fetch(&request.url).await {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            return Ok(DisplayResult {
+                status: err.status(),
+                fetch_error: Some(err),
+                ..Default::default()
+            })
+        }
+    };
+    let format = request.option.format.take().or(content_type);
+
+    Ok(display_image(state, image_body, format, request.option).await?)
+}
+
+fn current_image(state: State) -> (State, Response<Body>) {
+    let resp = match ImageStore::borrow_from(&state).current() {
+        Some(current) => create_response(
+            &state,
+            StatusCode::OK,
+            mime_type(current.image.format),
+            current.body,
+        ),
+        None => DisplayResult {
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        }
+        .into_response(&state),
+    };
+
+    (state, resp)
+}
+
+fn current_image_info(state: State) -> (State, impl IntoResponse) {
+    let resp = match ImageStore::borrow_from(&state).current() {
+        Some(current) => DisplayResult {
+            status: StatusCode::OK,
+            image: Some(current.image),
+            content_mode: Some(current.content_mode),
+            shown_at: Some(current.shown_at),
+            ..Default::default()
+        },
+        None => DisplayResult {
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        },
+    };
+
+    (state, resp)
 }
 
 #[derive(Clone, NewMiddleware, Debug, PartialEq, Default)]
@@ -95,10 +612,10 @@ impl Middleware for CORSMiddleware {
                 use hyper::header::*;
                 let headers = HeaderMap::borrow_from(&state);
                 for (key, value) in headers {
-                    let key = match key {
-                        &ACCESS_CONTROL_REQUEST_HEADERS => ACCESS_CONTROL_ALLOW_HEADERS,
-                        &ACCESS_CONTROL_REQUEST_METHOD => ACCESS_CONTROL_ALLOW_METHODS,
-                        &ORIGIN => ACCESS_CONTROL_ALLOW_ORIGIN,
+                    let key = match *key {
+                        ACCESS_CONTROL_REQUEST_HEADERS => ACCESS_CONTROL_ALLOW_HEADERS,
+                        ACCESS_CONTROL_REQUEST_METHOD => ACCESS_CONTROL_ALLOW_METHODS,
+                        ORIGIN => ACCESS_CONTROL_ALLOW_ORIGIN,
                         _ => continue,
                     };
                     response.headers_mut().insert(key, value.clone());
@@ -109,50 +626,1280 @@ impl Middleware for CORSMiddleware {
     }
 }
 
+fn index(state: State) -> (State, Response<Body>) {
+    let resp = create_response(&state, StatusCode::OK, mime::TEXT_HTML_UTF_8, INDEX_HTML);
+
+    (state, resp)
+}
+
 fn empty(state: State) -> (State, Response<Body>) {
     let resp = create_empty_response(&state, StatusCode::NO_CONTENT);
 
     (state, resp)
 }
 
+fn display_power(result: Result<(), PipelineError>, power: bool) -> DisplayPower {
+    match result {
+        Ok(()) => DisplayPower {
+            status: StatusCode::OK,
+            power: Some(power),
+        },
+        Err(err) => {
+            log::error!("Failed to change display power: {:?}", err);
+            DisplayPower {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                power: None,
+            }
+        }
+    }
+}
+
 fn display_off(state: State) -> (State, impl IntoResponse) {
-    crate::vc::tv::power_off();
-    let resp = DisplayPower {
-        status: StatusCode::OK,
-        power: Some(false),
+    let result = Backend::borrow_from(&state).power_off();
+    let resp = display_power(result, false);
+
+    (state, resp)
+}
+
+fn get_display_power(state: State) -> (State, impl IntoResponse) {
+    let resp = match Backend::borrow_from(&state).lock().display_info() {
+        Ok(info) => DisplayPower {
+            status: StatusCode::OK,
+            power: Some(info.power),
+        },
+        Err(err) => {
+            log::error!("Failed to get display state: {:?}", err);
+            DisplayPower {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                power: None,
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+fn display_info(state: State) -> (State, impl IntoResponse) {
+    let resp = match Backend::borrow_from(&state).lock().display_info() {
+        Ok(info) => DisplayInfoResult {
+            status: StatusCode::OK,
+            display: Some(info),
+        },
+        Err(err) => {
+            log::error!("Failed to get display state: {:?}", err);
+            DisplayInfoResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                display: None,
+            }
+        }
+    };
+
+    (state, resp)
+}
+
+fn brightness_result(result: Option<std::io::Result<BacklightInfo>>) -> BrightnessResult {
+    match result {
+        Some(Ok(backlight)) => BrightnessResult {
+            status: StatusCode::OK,
+            backlight: Some(backlight),
+        },
+        Some(Err(err)) => {
+            log::error!("Failed to access backlight: {:?}", err);
+            BrightnessResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                backlight: None,
+            }
+        }
+        None => BrightnessResult {
+            status: StatusCode::NOT_FOUND,
+            backlight: None,
+        },
+    }
+}
+
+fn get_brightness(state: State) -> (State, impl IntoResponse) {
+    let resp = brightness_result(Backlight::borrow_from(&state).info());
+
+    (state, resp)
+}
+
+fn set_brightness(mut state: State) -> (State, impl IntoResponse) {
+    let BrightnessOption { level, duration } = BrightnessOption::take_from(&mut state);
+    let duration = duration.map_or(DEFAULT_FADE_DURATION, Duration::from_millis);
+    let resp = match level {
+        0..=100 => brightness_result(Backlight::borrow_from(&state).fade(level, duration)),
+        _ => BrightnessResult {
+            status: StatusCode::BAD_REQUEST,
+            backlight: None,
+        },
     };
 
     (state, resp)
 }
 
+/// Polls every CEC logical address on a blocking thread, as each poll waits for an ack.
+async fn cec_topology(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let backend = Backend::borrow_from(state).clone();
+    let topology = tokio::task::spawn_blocking(move || backend.cec_topology()).await?;
+    Ok(match topology {
+        Some(Ok(topology)) => CecResult {
+            status: StatusCode::OK,
+            cec: Some(topology),
+        },
+        Some(Err(err)) => {
+            log::error!("Failed to query CEC devices: {:?}", err);
+            CecResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                cec: None,
+            }
+        }
+        None => CecResult {
+            status: StatusCode::NOT_FOUND,
+            cec: None,
+        },
+    })
+}
+
 fn display_on(state: State) -> (State, impl IntoResponse) {
-    crate::vc::tv::hdmi_power_on_preferred();
-    let resp = DisplayPower {
+    let result = Backend::borrow_from(&state).power_on();
+    let resp = display_power(result, true);
+
+    (state, resp)
+}
+
+fn get_schedule(state: State) -> (State, impl IntoResponse) {
+    let resp = ScheduleResult {
         status: StatusCode::OK,
-        power: Some(true),
+        schedule: Some(Scheduler::borrow_from(&state).schedule()),
+    };
+
+    (state, resp)
+}
+
+fn save_schedule(scheduler: &Scheduler, schedule: PowerSchedule) -> ScheduleResult {
+    match scheduler.set(schedule) {
+        Ok(()) => ScheduleResult {
+            status: StatusCode::OK,
+            schedule: Some(scheduler.schedule()),
+        },
+        Err(err) => {
+            log::error!("Failed to save power schedule: {:?}", err);
+            ScheduleResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+async fn set_schedule(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let body = Body::take_from(state);
+
+    let whole_body = hyper::body::to_bytes(body).await?;
+    let schedule = match serde_json::from_slice::<PowerSchedule>(&whole_body) {
+        Ok(schedule) if schedule.is_valid() => schedule,
+        _ => {
+            return Ok(ScheduleResult {
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            })
+        }
     };
 
+    Ok(save_schedule(Scheduler::borrow_from(state), schedule))
+}
+
+fn clear_schedule(state: State) -> (State, impl IntoResponse) {
+    let resp = save_schedule(Scheduler::borrow_from(&state), PowerSchedule::default());
+
     (state, resp)
 }
 
-pub fn router(pipeline: Pipeline) -> Router {
-    let middleware = StateMiddleware::new(pipeline);
+fn screenshot(state: State) -> (State, Response<Body>) {
+    let frame = Backend::borrow_from(&state).lock().screenshot();
+    let mut png = std::io::Cursor::new(Vec::new());
+    let result = frame.and_then(|frame| {
+        image::DynamicImage::ImageRgba8(frame)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .map_err(|e| PipelineError::ImageError(Operation::WriteFrameFailed, e))
+    });
+    let resp = match result {
+        Ok(()) => create_response(&state, StatusCode::OK, mime::IMAGE_PNG, png.into_inner()),
+        Err(err) => {
+            log::error!("Failed to take screenshot: {:?}", err);
+            create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    };
+
+    (state, resp)
+}
+
+fn playlist_result(slideshow: &Slideshow, status: StatusCode) -> PlaylistResult {
+    PlaylistResult {
+        status,
+        playlist: Some(slideshow.lock().clone()),
+        ..Default::default()
+    }
+}
+
+fn slide_not_found() -> PlaylistResult {
+    PlaylistResult {
+        status: StatusCode::NOT_FOUND,
+        ..Default::default()
+    }
+}
+
+fn get_slideshow(state: State) -> (State, impl IntoResponse) {
+    let resp = playlist_result(Slideshow::borrow_from(&state), StatusCode::OK);
+
+    (state, resp)
+}
+
+async fn add_slide(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let body = Body::take_from(state);
+    let query = ImageDisplayOption::take_from(state);
+
+    let whole_body = hyper::body::to_bytes(body).await?;
+    let format = request_format(state, query.format.clone());
+    let content_mode = ContentMode::from_str(query.mode.as_deref().unwrap_or_default());
+    let transition = Transition::from_str(
+        query.transition.as_deref().unwrap_or_default(),
+        query.duration,
+    );
+
+    // Adding decodes the image to check it, so keep that off the runtime
+    let slideshow = Slideshow::borrow_from(state).clone();
+    let added = tokio::task::spawn_blocking(move || {
+        let layout = request_layout(&query)?;
+        slideshow.add(whole_body, format, content_mode, transition, layout)
+    });
+    match added.await? {
+        Ok(slide) => Ok(PlaylistResult {
+            status: StatusCode::CREATED,
+            slide: Some(slide),
+            ..Default::default()
+        }),
+        Err(err) => Ok(PlaylistResult {
+            status: err.status(),
+            error: Some(err),
+            ..Default::default()
+        }),
+    }
+}
+
+fn remove_slide(mut state: State) -> (State, impl IntoResponse) {
+    let SlidePath { id } = SlidePath::take_from(&mut state);
+    let slideshow = Slideshow::borrow_from(&state);
+    let resp = match slideshow.remove(id) {
+        Some(slide) => PlaylistResult {
+            status: StatusCode::OK,
+            slide: Some(slide),
+            ..Default::default()
+        },
+        None => slide_not_found(),
+    };
+
+    (state, resp)
+}
+
+fn move_slide(mut state: State) -> (State, impl IntoResponse) {
+    let SlidePath { id } = SlidePath::take_from(&mut state);
+    let SlideMoveOption { index } = SlideMoveOption::take_from(&mut state);
+    let slideshow = Slideshow::borrow_from(&state);
+    let resp = match slideshow.move_to(id, index) {
+        Some(()) => playlist_result(slideshow, StatusCode::OK),
+        None => slide_not_found(),
+    };
+
+    (state, resp)
+}
+
+fn set_slideshow_interval(mut state: State) -> (State, impl IntoResponse) {
+    let SlideshowIntervalOption { seconds } = SlideshowIntervalOption::take_from(&mut state);
+    let slideshow = Slideshow::borrow_from(&state);
+    let resp = match seconds {
+        0 => PlaylistResult {
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
+        },
+        seconds => {
+            slideshow.set_interval(seconds);
+            playlist_result(slideshow, StatusCode::OK)
+        }
+    };
+
+    (state, resp)
+}
+
+/// Runs `control` on a blocking thread, as changing slides may play a transition.
+fn control_slideshow<F>(state: &State, control: F) -> JoinHandle<PlaylistResult>
+where
+    F: FnOnce(&Slideshow) + Send + 'static,
+{
+    let slideshow = Slideshow::borrow_from(state).clone();
+    tokio::task::spawn_blocking(move || {
+        control(&slideshow);
+        playlist_result(&slideshow, StatusCode::OK)
+    })
+}
+
+async fn play_slideshow(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    Ok(control_slideshow(state, Slideshow::play).await?)
+}
+
+fn pause_slideshow(state: State) -> (State, impl IntoResponse) {
+    let slideshow = Slideshow::borrow_from(&state);
+    slideshow.pause();
+    let resp = playlist_result(slideshow, StatusCode::OK);
+
+    (state, resp)
+}
+
+async fn next_slide(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    Ok(control_slideshow(state, Slideshow::next).await?)
+}
+
+async fn previous_slide(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    Ok(control_slideshow(state, Slideshow::previous).await?)
+}
+
+fn region_result(result: Option<Result<RegionInfo, PipelineError>>) -> RegionResult {
+    match result {
+        Some(Ok(region)) => RegionResult {
+            status: StatusCode::OK,
+            region: Some(region),
+            ..Default::default()
+        },
+        Some(Err(err)) => {
+            log::error!("Failed to render region: {:?}", err);
+            RegionResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..Default::default()
+            }
+        }
+        None => RegionResult {
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        },
+    }
+}
+
+fn get_regions(state: State) -> (State, impl IntoResponse) {
+    let resp = RegionResult {
+        status: StatusCode::OK,
+        regions: Some(Regions::borrow_from(&state).list()),
+        ..Default::default()
+    };
+
+    (state, resp)
+}
+
+fn set_region(mut state: State) -> (State, impl IntoResponse) {
+    let RegionPath { name } = RegionPath::take_from(&mut state);
+    let RegionOption {
+        x,
+        y,
+        w,
+        h,
+        layer,
+        opacity,
+    } = RegionOption::take_from(&mut state);
+    let viewport = Backend::borrow_from(&state).lock().viewport();
+    let resp = match Region::new(DisplayRect { x, y, w, h }, viewport, layer, opacity) {
+        Some(region) => region_result(Some(Regions::borrow_from(&state).set(&name, region))),
+        None => RegionResult {
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
+        },
+    };
+
+    (state, resp)
+}
+
+async fn show_region(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+    let body = Body::take_from(state);
+    let RegionPath { name } = RegionPath::take_from(state);
+    let query = ImageDisplayOption::take_from(state);
+
+    let whole_body = hyper::body::to_bytes(body).await?;
+    let format = request_format(state, query.format.clone());
+    Ok(display_region(state, name, whole_body, format, query).await?)
+}
+
+/// Decodes and renders the region's image on a blocking thread, like `display_image`.
+fn display_region(
+    state: &State,
+    name: String,
+    whole_body: Bytes,
+    format: Option<String>,
+    query: ImageDisplayOption,
+) -> JoinHandle<RegionResult> {
+    let max_size = Backend::borrow_from(state).max_image_size();
+    let regions = Regions::borrow_from(state).clone();
+    tokio::task::spawn_blocking(move || {
+        let layout = match request_layout(&query) {
+            Ok(layout) => layout,
+            Err(err) => {
+                return RegionResult {
+                    status: err.status(),
+                    error: Some(err),
+                    ..Default::default()
+                }
+            }
+        };
+        let mut image = match load_image(whole_body, format.as_deref(), max_size) {
+            Ok(image) => image,
+            Err(err) => {
+                return RegionResult {
+                    status: err.status(),
+                    error: Some(err),
+                    ..Default::default()
+                }
+            }
+        };
+        layout.apply(&mut image);
+        let content_mode = ContentMode::from_str(query.mode.as_deref().unwrap_or_default());
+
+        region_result(regions.show(&name, image, content_mode))
+    })
+}
+
+fn remove_region(mut state: State) -> (State, impl IntoResponse) {
+    let RegionPath { name } = RegionPath::take_from(&mut state);
+    let resp = region_result(Regions::borrow_from(&state).remove(&name));
+
+    (state, resp)
+}
+
+fn request_overlay(query: OverlayOption, viewport: (u32, u32)) -> Option<Overlay> {
+    let content = match query.kind.as_str() {
+        "text" => OverlayContent::Text {
+            text: query
+                .text
+                .filter(|text| text.chars().count() <= MAX_TEXT_LENGTH)?,
+        },
+        "clock" => {
+            let format = query.format.unwrap_or(DEFAULT_CLOCK_FORMAT.to_string());
+            if format.chars().count() > MAX_TEXT_LENGTH || !is_valid_clock_format(&format) {
+                return None;
+            }
+            OverlayContent::Clock { format }
+        }
+        "caption" => OverlayContent::Caption,
+        _ => return None,
+    };
+    let color = |color: Option<String>, default| match color {
+        Some(color) => parse_color(&color),
+        None => Some(default),
+    };
+
+    Some(Overlay {
+        content,
+        anchor: Anchor::from_str(query.anchor.as_deref().unwrap_or_default()),
+        size: match query.size.filter(|size| *size > 0) {
+            // Larger text would be rasterized just to be clipped away
+            Some(size) if size > viewport.1 => return None,
+            size => size,
+        },
+        color: color(query.color, image::Rgba([0xff; 4]))?,
+        background: color(query.background, image::Rgba([0, 0, 0, 0x80]))?,
+    })
+}
+
+fn overlay_result(result: Result<Option<Overlay>, PipelineError>) -> OverlayResult {
+    match result {
+        Ok(Some(overlay)) => OverlayResult {
+            status: StatusCode::OK,
+            overlay: Some(overlay),
+            ..Default::default()
+        },
+        Ok(None) => OverlayResult {
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        },
+        Err(err) => {
+            log::error!("Failed to redraw overlays: {:?}", err);
+            OverlayResult {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                ..Default::default()
+            }
+        }
+    }
+}
+
+fn get_overlays(state: State) -> (State, impl IntoResponse) {
+    let resp = OverlayResult {
+        status: StatusCode::OK,
+        overlays: Some(Backend::borrow_from(&state).overlays()),
+        ..Default::default()
+    };
+
+    (state, resp)
+}
+
+fn set_overlay(mut state: State) -> (State, impl IntoResponse) {
+    let OverlayPath { name } = OverlayPath::take_from(&mut state);
+    let query = OverlayOption::take_from(&mut state);
+    let backend = Backend::borrow_from(&state);
+    let viewport = backend.lock().viewport();
+    let resp = match request_overlay(query, viewport) {
+        Some(overlay) => overlay_result(
+            backend
+                .set_overlay(&name, overlay.clone())
+                .map(|_| Some(overlay)),
+        ),
+        None => OverlayResult {
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
+        },
+    };
+
+    (state, resp)
+}
+
+fn remove_overlay(mut state: State) -> (State, impl IntoResponse) {
+    let OverlayPath { name } = OverlayPath::take_from(&mut state);
+    let resp = overlay_result(Backend::borrow_from(&state).remove_overlay(&name));
+
+    (state, resp)
+}
+
+fn clear_overlays(state: State) -> (State, impl IntoResponse) {
+    let resp = match Backend::borrow_from(&state).clear_overlays() {
+        Ok(()) => OverlayResult {
+            status: StatusCode::OK,
+            overlays: Some(Default::default()),
+            ..Default::default()
+        },
+        Err(err) => overlay_result(Err(err)),
+    };
+
+    (state, resp)
+}
+
+pub fn router(
+    backend: Backend,
+    slideshow: Slideshow,
+    store: ImageStore,
+    regions: Regions,
+    scheduler: Scheduler,
+    backlight: Backlight,
+) -> Router {
     let pipeline = new_pipeline()
         .add(RequestLogger::new(log::Level::Info))
-        .add(middleware)
+        .add(StateMiddleware::new(backend))
+        .add(StateMiddleware::new(slideshow))
+        .add(StateMiddleware::new(store))
+        .add(StateMiddleware::new(regions))
+        .add(StateMiddleware::new(scheduler))
+        .add(StateMiddleware::new(backlight))
         .add(CORSMiddleware::default())
         .build();
     let (chain, pipelines) = single_pipeline(pipeline);
 
     build_router(chain, pipelines, |route| {
+        route.get("/").to(index);
+
         route.options("/image/show").to(empty);
 
         route
             .post("/image/show")
             .with_query_string_extractor::<ImageDisplayOption>()
             .to_async_borrowing(show_image);
+        route.options("/image/show-url").to(empty);
+        route.post("/image/show-url").to_async_borrowing(show_url);
+        route.get("/image/current").to(current_image);
+        route.get("/image/current.json").to(current_image_info);
 
+        route.get("/display/power").to(get_display_power);
+        route.get("/display/info").to(display_info);
+        route.get("/display/cec").to_async_borrowing(cec_topology);
         route.post("/display/power/on").to(display_on);
         route.post("/display/power/off").to(display_off);
+        route.get("/display/screenshot").to(screenshot);
+
+        route.options("/display/brightness").to(empty);
+        route.get("/display/brightness").to(get_brightness);
+        route
+            .put("/display/brightness")
+            .with_query_string_extractor::<BrightnessOption>()
+            .to(set_brightness);
+
+        route.options("/display/schedule").to(empty);
+        route.get("/display/schedule").to(get_schedule);
+        route
+            .put("/display/schedule")
+            .to_async_borrowing(set_schedule);
+        route.delete("/display/schedule").to(clear_schedule);
+
+        route.options("/regions/:name").to(empty);
+        route.options("/regions/:name/show").to(empty);
+
+        route.get("/regions").to(get_regions);
+        route
+            .put("/regions/:name")
+            .with_path_extractor::<RegionPath>()
+            .with_query_string_extractor::<RegionOption>()
+            .to(set_region);
+        route
+            .post("/regions/:name/show")
+            .with_path_extractor::<RegionPath>()
+            .with_query_string_extractor::<ImageDisplayOption>()
+            .to_async_borrowing(show_region);
+        route
+            .delete("/regions/:name")
+            .with_path_extractor::<RegionPath>()
+            .to(remove_region);
+
+        route.options("/overlays").to(empty);
+        route.options("/overlays/:name").to(empty);
+
+        route.get("/overlays").to(get_overlays);
+        route.delete("/overlays").to(clear_overlays);
+        route
+            .put("/overlays/:name")
+            .with_path_extractor::<OverlayPath>()
+            .with_query_string_extractor::<OverlayOption>()
+            .to(set_overlay);
+        route
+            .delete("/overlays/:name")
+            .with_path_extractor::<OverlayPath>()
+            .to(remove_overlay);
+
+        route.options("/slideshow/images").to(empty);
+        route.options("/slideshow/images/:id").to(empty);
+        route.options("/slideshow/interval").to(empty);
+
+        route.get("/slideshow").to(get_slideshow);
+        route
+            .post("/slideshow/images")
+            .with_query_string_extractor::<ImageDisplayOption>()
+            .to_async_borrowing(add_slide);
+        route
+            .delete("/slideshow/images/:id")
+            .with_path_extractor::<SlidePath>()
+            .to(remove_slide);
+        route
+            .post("/slideshow/images/:id/move")
+            .with_path_extractor::<SlidePath>()
+            .with_query_string_extractor::<SlideMoveOption>()
+            .to(move_slide);
+        route
+            .put("/slideshow/interval")
+            .with_query_string_extractor::<SlideshowIntervalOption>()
+            .to(set_slideshow_interval);
+        route
+            .post("/slideshow/play")
+            .to_async_borrowing(play_slideshow);
+        route.post("/slideshow/pause").to(pause_slideshow);
+        route.post("/slideshow/next").to_async_borrowing(next_slide);
+        route
+            .post("/slideshow/previous")
+            .to_async_borrowing(previous_slide);
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::*;
+    use gotham::test::TestServer;
+    use image::{ImageOutputFormat, RgbaImage};
+    use tempfile::TempDir;
+
+    struct Fixture {
+        server: TestServer,
+        backend: MockBackend,
+        dir: TempDir,
+    }
+
+    fn fixture(mock: MockBackend) -> Fixture {
+        let dir = TempDir::new().unwrap();
+        let backend = Backend::new(mock.clone());
+        let store = ImageStore::new(Some(dir.path().to_path_buf()));
+        let router = router(
+            backend.clone(),
+            Slideshow::new(backend.clone(), store.clone(), 10),
+            store,
+            Regions::new(backend.clone()),
+            Scheduler::new(backend, dir.path().to_path_buf()),
+            Backlight::default(),
+        );
+        Fixture {
+            server: TestServer::new(router).unwrap(),
+            backend: mock,
+            dir,
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut body = std::io::Cursor::new(Vec::new());
+        RgbaImage::new(width, height)
+            .write_to(&mut body, ImageOutputFormat::Png)
+            .unwrap();
+        body.into_inner()
+    }
+
+    fn gif(width: u32, height: u32, frames: usize) -> Vec<u8> {
+        use image::codecs::gif::GifEncoder;
+        use image::Frame;
+
+        let mut body = Vec::new();
+        let mut encoder = GifEncoder::new(&mut body);
+        encoder
+            .encode_frames((0..frames).map(|i| {
+                let pixel = image::Rgba([(i % 256) as u8, 0, 0, 255]);
+                Frame::new(RgbaImage::from_pixel(width, height, pixel))
+            }))
+            .unwrap();
+        drop(encoder);
+        body
+    }
+
+    fn post(
+        fixture: &Fixture,
+        uri: &str,
+        body: Vec<u8>,
+        mime: mime::Mime,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = fixture
+            .server
+            .client()
+            .post(format!("http://localhost{}", uri), body, mime)
+            .perform()
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            serde_json::from_slice(&response.read_body().unwrap()).unwrap(),
+        )
+    }
+
+    fn get(fixture: &Fixture, uri: &str) -> (StatusCode, Vec<u8>) {
+        let response = fixture
+            .server
+            .client()
+            .get(format!("http://localhost{}", uri))
+            .perform()
+            .unwrap();
+        (response.status(), response.read_body().unwrap())
+    }
+
+    fn put(fixture: &Fixture, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = fixture
+            .server
+            .client()
+            .put(
+                format!("http://localhost{}", uri),
+                Body::empty(),
+                mime::TEXT_PLAIN,
+            )
+            .perform()
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            serde_json::from_slice(&response.read_body().unwrap()).unwrap(),
+        )
+    }
+
+    fn delete(fixture: &Fixture, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = fixture
+            .server
+            .client()
+            .delete(format!("http://localhost{}", uri))
+            .perform()
+            .unwrap();
+        let status = response.status();
+        (
+            status,
+            serde_json::from_slice(&response.read_body().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn shows_image() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let body = png(32, 16);
+        let (status, result) = post(
+            &fixture,
+            "/image/show?mode=aspect_fit",
+            body.clone(),
+            mime::IMAGE_PNG,
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["image"]["width"], 32);
+        assert_eq!(result["content_mode"], "aspect_fit");
+        assert_eq!(
+            fixture.backend.calls(),
+            vec![Call::RenderImage {
+                size: (32, 16),
+                content_mode: ContentMode::Aspect(AspectMode::Fit),
+                transition: TransitionKind::None,
+            }]
+        );
+
+        let (status, current) = get(&fixture, "/image/current");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(current, body);
+        let (status, info) = get(&fixture, "/image/current.json");
+        assert_eq!(status, StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_slice(&info).unwrap();
+        assert_eq!(info["image"]["height"], 16);
+
+        let meta = std::fs::read(fixture.dir.path().join("current.json")).unwrap();
+        let meta: serde_json::Value = serde_json::from_slice(&meta).unwrap();
+        assert_eq!(meta["format"], "image/png");
+        assert_eq!(meta["content_mode"], "aspect_fit");
+        assert_eq!(
+            std::fs::read(fixture.dir.path().join("current.img")).unwrap(),
+            body
+        );
+    }
+
+    #[test]
+    fn shows_multipart_upload() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let mut body = b"--x\r\nContent-Disposition: form-data; name=\"mode\"\r\n\r\nfill\r\n\
+            --x\r\nContent-Disposition: form-data; name=\"image\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n"
+            .to_vec();
+        body.extend(png(8, 8));
+        body.extend(b"\r\n--x--\r\n");
+        let mime = "multipart/form-data; boundary=x".parse().unwrap();
+        let (status, result) = post(&fixture, "/image/show", body, mime);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["content_mode"], "scaletofill");
+        assert_eq!(fixture.backend.calls().len(), 1);
+    }
+
+    #[test]
+    fn rejects_unknown_format() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let (status, result) = post(&fixture, "/image/show", b"hello".to_vec(), mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(result["status"], "415");
+        assert!(result["error"]["image_error"].is_string());
+        assert!(fixture.backend.calls().is_empty());
+        assert_eq!(
+            get(&fixture, "/image/current.json").0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_transition() {
+        let fixture = fixture(MockBackend::without_transition(
+            64,
+            64,
+            TransitionKind::Crossfade,
+        ));
+        let (status, result) = post(
+            &fixture,
+            "/image/show?transition=crossfade",
+            png(8, 8),
+            mime::IMAGE_PNG,
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(result["error"]["image_error"].is_string());
+        assert!(fixture.backend.calls().is_empty());
+
+        let (status, _) = post(
+            &fixture,
+            "/image/show?transition=fade",
+            png(8, 8),
+            mime::IMAGE_PNG,
+        );
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn downscales_animation_frames() {
+        let body = Bytes::from(gif(128, 64, 3));
+        let image = load_image(body, Some("image/gif"), (32, 32)).unwrap();
+        assert_eq!(image.frames().len(), 3);
+        assert_eq!(image.size(), (64, 32));
+        assert!(image
+            .frames()
+            .iter()
+            .all(|frame| frame.image.size() == (64, 32)));
+    }
+
+    #[test]
+    fn rejects_too_many_animation_frames() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let body = gif(1, 1, MAX_ANIMATION_FRAMES + 1);
+        let (status, result) = post(&fixture, "/image/show", body, mime::IMAGE_GIF);
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(result["error"]["image_error"].is_string());
+        assert!(fixture.backend.calls().is_empty());
+    }
+
+    #[test]
+    fn limits_overlay_size_to_viewport() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        let put = |size: u32| {
+            fixture
+                .server
+                .client()
+                .put(
+                    format!(
+                        "http://localhost/overlays/note?type=text&text=hi&size={}",
+                        size
+                    ),
+                    Body::empty(),
+                    mime::TEXT_PLAIN,
+                )
+                .perform()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(put(48), StatusCode::OK);
+        assert_eq!(put(49), StatusCode::BAD_REQUEST);
+        assert_eq!(put(u32::MAX), StatusCode::BAD_REQUEST);
+
+        let put_text = |text: String| {
+            fixture
+                .server
+                .client()
+                .put(
+                    format!("http://localhost/overlays/note?type=text&text={}", text),
+                    Body::empty(),
+                    mime::TEXT_PLAIN,
+                )
+                .perform()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(put_text("W".repeat(MAX_TEXT_LENGTH)), StatusCode::OK);
+        assert_eq!(
+            put_text("W".repeat(MAX_TEXT_LENGTH + 1)),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_image() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let mut body = png(8, 8);
+        body.truncate(body.len() / 2);
+        let (status, _) = post(&fixture, "/image/show", body, mime::IMAGE_PNG);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(fixture.backend.calls().is_empty());
+    }
+
+    #[test]
+    fn rejects_multipart_without_image() {
+        let fixture = fixture(MockBackend::new(64, 64));
+        let body = b"--x\r\nContent-Disposition: form-data; name=\"mode\"\r\n\r\nfill\r\n--x--\r\n";
+        let mime = "multipart/form-data; boundary=x".parse().unwrap();
+        let (status, _) = post(&fixture, "/image/show", body.to_vec(), mime);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(fixture.backend.calls().is_empty());
+    }
+
+    #[test]
+    fn reports_render_failure() {
+        let fixture = fixture(MockBackend::failing(64, 64));
+        let (status, _) = post(&fixture, "/image/show", png(8, 8), mime::IMAGE_PNG);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(fixture.backend.calls().len(), 1);
+        assert!(!fixture.dir.path().join("current.json").exists());
+        assert_eq!(
+            get(&fixture, "/image/current.json").0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn shows_region() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        let (status, result) = put(&fixture, "/regions/inset?x=8&y=4&w=32&h=16&layer=2");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["region"]["name"], "inset");
+        assert_eq!(result["region"]["layer"], 2);
+        assert!(result["region"]["image"].is_null());
+        // Nothing to draw until the region shows an image
+        assert!(fixture.backend.calls().is_empty());
+
+        let (status, result) = post(
+            &fixture,
+            "/regions/inset/show?mode=aspect_fit",
+            png(16, 8),
+            mime::IMAGE_PNG,
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["region"]["image"]["width"], 16);
+        assert_eq!(result["region"]["content_mode"], "aspect_fit");
+        let region = Region {
+            x: 8,
+            y: 4,
+            w: 32,
+            h: 16,
+            layer: 2,
+            opacity: 1f32,
+        };
+        assert_eq!(
+            fixture.backend.calls(),
+            vec![Call::RenderRegion {
+                name: "inset".to_string(),
+                region,
+                size: (16, 8),
+            }]
+        );
+
+        // Moving the region redraws its image
+        let (status, _) = put(&fixture, "/regions/inset?x=0&y=0&w=32&h=16&layer=2");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            fixture.backend.calls().last(),
+            Some(&Call::RenderRegion {
+                name: "inset".to_string(),
+                region: Region {
+                    x: 0,
+                    y: 0,
+                    ..region
+                },
+                size: (16, 8),
+            })
+        );
+
+        let (status, list) = get(&fixture, "/regions");
+        assert_eq!(status, StatusCode::OK);
+        let list: serde_json::Value = serde_json::from_slice(&list).unwrap();
+        assert_eq!(list["regions"][0]["name"], "inset");
+
+        let (status, _) = delete(&fixture, "/regions/inset");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            fixture.backend.calls().last(),
+            Some(&Call::RemoveRegion("inset".to_string()))
+        );
+        let (_, list) = get(&fixture, "/regions");
+        let list: serde_json::Value = serde_json::from_slice(&list).unwrap();
+        assert_eq!(list["regions"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn rejects_missing_region() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        let (status, _) = post(&fixture, "/regions/none/show", png(8, 8), mime::IMAGE_PNG);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(delete(&fixture, "/regions/none").0, StatusCode::NOT_FOUND);
+        assert!(fixture.backend.calls().is_empty());
+    }
+
+    #[test]
+    fn clamps_region_to_viewport() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        let (status, result) = put(&fixture, "/regions/edge?x=-8&y=40&w=32&h=16");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["region"]["x"], 0);
+        assert_eq!(result["region"]["y"], 40);
+        assert_eq!(result["region"]["w"], 24);
+        assert_eq!(result["region"]["h"], 8);
+
+        let (status, _) = put(&fixture, "/regions/outside?x=64&y=0&w=10&h=10");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = put(&fixture, "/regions/empty?x=0&y=0&w=0&h=10");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (_, list) = get(&fixture, "/regions");
+        let list: serde_json::Value = serde_json::from_slice(&list).unwrap();
+        assert_eq!(list["regions"].as_array().unwrap().len(), 1);
+    }
+
+    fn slide_ids(playlist: &serde_json::Value) -> Vec<u64> {
+        playlist["slides"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|slide| slide["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn manages_slideshow() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        for (id, width) in [(0, 16), (1, 24), (2, 32)] {
+            let (status, result) = post(
+                &fixture,
+                "/slideshow/images?mode=fill",
+                png(width, 8),
+                mime::IMAGE_PNG,
+            );
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(result["slide"]["id"], id);
+            assert_eq!(result["slide"]["width"], width);
+            assert_eq!(result["slide"]["content_mode"], "scaletofill");
+        }
+        // Slides are only decoded to check them until the slideshow plays
+        assert!(fixture.backend.calls().is_empty());
+
+        let (status, result) = get(&fixture, "/slideshow");
+        assert_eq!(status, StatusCode::OK);
+        let result: serde_json::Value = serde_json::from_slice(&result).unwrap();
+        assert_eq!(slide_ids(&result["playlist"]), vec![0, 1, 2]);
+        assert_eq!(result["playlist"]["playing"], false);
+
+        let (status, result) = post(
+            &fixture,
+            "/slideshow/images/2/move?index=0",
+            vec![],
+            mime::TEXT_PLAIN,
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(slide_ids(&result["playlist"]), vec![2, 0, 1]);
+
+        let (status, result) = delete(&fixture, "/slideshow/images/0");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["slide"]["id"], 0);
+        let (status, _) = delete(&fixture, "/slideshow/images/0");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = post(
+            &fixture,
+            "/slideshow/images/0/move?index=0",
+            vec![],
+            mime::TEXT_PLAIN,
+        );
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, result) = post(&fixture, "/slideshow/play", vec![], mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["playlist"]["playing"], true);
+        assert_eq!(result["playlist"]["current"], 2);
+        assert_eq!(
+            fixture.backend.calls(),
+            vec![Call::RenderImage {
+                size: (32, 8),
+                content_mode: ContentMode::ScaleToFill,
+                transition: TransitionKind::None,
+            }]
+        );
+
+        let (status, result) = post(&fixture, "/slideshow/pause", vec![], mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["playlist"]["playing"], false);
+        assert_eq!(slide_ids(&result["playlist"]), vec![2, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_slide() {
+        let fixture = fixture(MockBackend::without_transition(
+            64,
+            48,
+            TransitionKind::Crossfade,
+        ));
+        let (status, result) = post(
+            &fixture,
+            "/slideshow/images",
+            b"hello".to_vec(),
+            mime::TEXT_PLAIN,
+        );
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(result["error"]["image_error"].is_string());
+        let (status, _) = post(
+            &fixture,
+            "/slideshow/images?transition=crossfade",
+            png(8, 8),
+            mime::IMAGE_PNG,
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, result) = get(&fixture, "/slideshow");
+        let result: serde_json::Value = serde_json::from_slice(&result).unwrap();
+        assert!(slide_ids(&result["playlist"]).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_orientation() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        // Not every rejection has a JSON body, e.g. when the query does not parse
+        let show = |uri: &str| {
+            fixture
+                .server
+                .client()
+                .post(
+                    format!("http://localhost{}", uri),
+                    png(8, 4),
+                    mime::IMAGE_PNG,
+                )
+                .perform()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(show("/image/show?rotate=90&flip=h"), StatusCode::OK);
+        assert_eq!(show("/image/show?rotate=45"), StatusCode::BAD_REQUEST);
+        assert_eq!(show("/image/show?rotate=abc"), StatusCode::BAD_REQUEST);
+        assert_eq!(show("/image/show?flip=diagonal"), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            show("/slideshow/images?rotate=360"),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(fixture.backend.calls().len(), 1);
+
+        let (_, result) = post(
+            &fixture,
+            "/image/show?flip=diagonal",
+            png(8, 4),
+            mime::IMAGE_PNG,
+        );
+        assert!(result["error"]["image_error"].is_string());
+
+        let mut body = b"--x\r\nContent-Disposition: form-data; name=\"rotate\"\r\n\r\nabc\r\n\
+            --x\r\nContent-Disposition: form-data; name=\"image\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n"
+            .to_vec();
+        body.extend(png(8, 8));
+        body.extend(b"\r\n--x--\r\n");
+        let mime = "multipart/form-data; boundary=x".parse().unwrap();
+        let (status, _) = post(&fixture, "/image/show", body, mime);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(fixture.backend.calls().len(), 1);
+    }
+
+    #[test]
+    fn switches_display_power() {
+        let fixture = fixture(MockBackend::new(64, 48));
+        let power = |fixture: &Fixture| {
+            let (status, body) = get(fixture, "/display/power");
+            assert_eq!(status, StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["power"].clone()
+        };
+        assert_eq!(power(&fixture), true);
+
+        let (status, result) = post(&fixture, "/display/power/off", vec![], mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["power"], false);
+        assert_eq!(fixture.backend.calls(), vec![Call::PowerOff]);
+        assert_eq!(power(&fixture), false);
+
+        let (status, info) = get(&fixture, "/display/info");
+        assert_eq!(status, StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_slice(&info).unwrap();
+        assert_eq!(info["display"]["attached"], true);
+        assert_eq!(info["display"]["power"], false);
+        assert_eq!(info["display"]["width"], 64);
+        assert_eq!(info["display"]["height"], 48);
+
+        let (status, result) = post(&fixture, "/display/power/on", vec![], mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result["power"], true);
+        assert_eq!(power(&fixture), true);
+    }
+
+    #[test]
+    fn reports_display_power_failure() {
+        let fixture = fixture(MockBackend::failing(64, 48));
+        let (status, result) = post(&fixture, "/display/power/on", vec![], mime::TEXT_PLAIN);
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(result["power"].is_null());
+        assert_eq!(
+            get(&fixture, "/display/power").0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            get(&fixture, "/display/info").0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
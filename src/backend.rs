@@ -0,0 +1,398 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+#[cfg(test)]
+pub mod mock;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cec::Cec;
+use crate::display::{cec::*, frame::*, image::*, overlay::*, power::*, region::*, transition::*};
+use crate::error::{Operation, PipelineError};
+use gotham_derive::*;
+use image::RgbaImage;
+
+/// Milliseconds to wait for the display to take a rendered image.
+pub const RENDER_TIMEOUT: i32 = 2000;
+
+pub trait DisplayBackend: Send {
+    fn init(&mut self) -> Result<(), PipelineError>;
+
+    fn destroy(&mut self) -> Result<(), PipelineError>;
+
+    fn render_image(
+        &mut self,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        transition: Transition,
+        timeout: i32,
+    ) -> Result<(), PipelineError>;
+
+    fn render_region(
+        &mut self,
+        name: &str,
+        region: Region,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        timeout: i32,
+    ) -> Result<(), PipelineError>;
+
+    fn remove_region(&mut self, name: &str) -> Result<(), PipelineError>;
+
+    fn power_on(&mut self) -> Result<(), PipelineError>;
+
+    fn power_off(&mut self) -> Result<(), PipelineError>;
+
+    fn display_info(&mut self) -> Result<DisplayInfo, PipelineError>;
+
+    /// The frame on screen, in viewport orientation.
+    fn screenshot(&mut self) -> Result<RgbaImage, PipelineError>;
+
+    fn viewport(&self) -> (u32, u32);
+
+    fn supports_transition(&self, _kind: TransitionKind) -> bool {
+        true
+    }
+
+    /// The frame on screen before rotation, for backends whose transitions are blended
+    /// on the CPU by `Backend`. Others play transitions in `render_image`.
+    fn frame(&self) -> Option<RgbaImage> {
+        None
+    }
+
+    /// Composites the image and the regions over it at the viewport size.
+    fn compose(&self, image: &DisplayImage, content_mode: ContentMode) -> RgbaImage {
+        composite(image, content_mode, self.viewport())
+    }
+
+    /// Shows a frame from `compose` as is, e.g. a step of a transition.
+    fn show_frame(&mut self, _frame: &RgbaImage) -> Result<(), PipelineError> {
+        Err(PipelineError::Assertion(Operation::WriteFrameFailed))
+    }
+}
+
+#[derive(Clone, StateData)]
+pub struct Backend {
+    inner: Arc<Mutex<Box<dyn DisplayBackend>>>,
+    generation: Arc<AtomicUsize>,
+    max_image_size: Option<(u32, u32)>,
+    overlays: Arc<Mutex<BTreeMap<String, Overlay>>>,
+    current: Arc<Mutex<Option<(DisplayImage, ContentMode)>>>,
+    cec: Option<Arc<Mutex<Cec>>>,
+    unsupported_transitions: Vec<TransitionKind>,
+}
+
+impl Backend {
+    pub fn new<T: DisplayBackend + 'static>(backend: T) -> Backend {
+        let unsupported_transitions = [
+            TransitionKind::Crossfade,
+            TransitionKind::FadeThroughBlack,
+            TransitionKind::Slide,
+        ]
+        .iter()
+        .copied()
+        .filter(|kind| !backend.supports_transition(*kind))
+        .collect();
+        Backend {
+            inner: Arc::new(Mutex::new(Box::new(backend))),
+            generation: Arc::new(AtomicUsize::new(0)),
+            max_image_size: None,
+            overlays: Arc::default(),
+            current: Arc::default(),
+            cec: None,
+            unsupported_transitions,
+        }
+    }
+
+    /// Whether the backend can play the transition, checked without waiting for the lock.
+    pub fn supports_transition(&self, transition: Transition) -> bool {
+        transition.is_none() || !self.unsupported_transitions.contains(&transition.kind)
+    }
+
+    pub fn set_max_image_size(&mut self, max_image_size: Option<(u32, u32)>) {
+        self.max_image_size = max_image_size;
+    }
+
+    /// Images larger than this are downscaled while decoding (default: viewport).
+    pub fn max_image_size(&self) -> (u32, u32) {
+        self.max_image_size
+            .unwrap_or_else(|| self.lock().viewport())
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Box<dyn DisplayBackend>> {
+        // A panic while rendering must not take the display down with it
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_cec(&mut self, cec: Cec) {
+        self.cec = Some(Arc::new(Mutex::new(cec)));
+    }
+
+    /// Turns the display on, and wakes the TV over CEC if enabled.
+    pub fn power_on(&self) -> Result<(), PipelineError> {
+        self.lock().power_on()?;
+        if let Some(cec) = &self.cec {
+            if let Err(err) = cec.lock().unwrap_or_else(PoisonError::into_inner).wake() {
+                log::warn!("Failed to wake TV over CEC: {:?}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts the TV into standby over CEC if enabled, and turns the display off.
+    pub fn power_off(&self) -> Result<(), PipelineError> {
+        if let Some(cec) = &self.cec {
+            if let Err(err) = cec.lock().unwrap_or_else(PoisonError::into_inner).standby() {
+                log::warn!("Failed to put TV into standby over CEC: {:?}", err);
+            }
+        }
+        self.lock().power_off()
+    }
+
+    /// Devices on the CEC bus, or `None` without CEC.
+    pub fn cec_topology(&self) -> Option<io::Result<CecTopology>> {
+        self.cec.as_ref().map(|cec| {
+            cec.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .topology()
+        })
+    }
+
+    /// Renders the image, and keeps playing its frames until another image is rendered.
+    pub fn render(
+        &self,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        transition: Transition,
+        timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        *self.current.lock().unwrap() = Some((image.clone(), content_mode));
+        let transition = self.blend_transition(generation, image, content_mode, transition)?;
+        if self.generation.load(Ordering::SeqCst) != generation {
+            // Another image was rendered during the transition
+            return Ok(());
+        }
+        self.render_frame(
+            &mut self.lock(),
+            image,
+            content_mode,
+            image.caption(),
+            transition,
+            timeout,
+        )?;
+
+        if image.is_animated() {
+            let backend = self.clone();
+            let frames = image.frames();
+            let caption = image.caption().clone();
+            std::thread::spawn(move || {
+                backend.animate(generation, frames, content_mode, caption, timeout)
+            });
+        }
+        Ok(())
+    }
+
+    /// Plays the transition on the CPU if the backend can show blended frames, taking the
+    /// lock for one frame at a time. Returns the transition left for the backend to play.
+    fn blend_transition(
+        &self,
+        generation: usize,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        transition: Transition,
+    ) -> Result<Transition, PipelineError> {
+        if transition.is_none() {
+            return Ok(transition);
+        }
+        let (from, to) = {
+            let backend = self.lock();
+            let from = match backend.frame() {
+                Some(from) => from,
+                None => return Ok(transition),
+            };
+            let viewport = backend.viewport();
+            let to = match self.with_overlays(viewport, image, content_mode, image.caption()) {
+                Some(frame) => backend.compose(&frame, ContentMode::ScaleToFill),
+                None => backend.compose(image, content_mode),
+            };
+            (from, to)
+        };
+
+        if from.dimensions() == to.dimensions() {
+            transition.each_frame(|progress| {
+                if self.generation.load(Ordering::SeqCst) != generation {
+                    return Ok(());
+                }
+                let frame = transition.blend(&from, &to, progress);
+                let mut backend = self.lock();
+                match self.generation.load(Ordering::SeqCst) == generation {
+                    true => backend.show_frame(&frame),
+                    false => Ok(()),
+                }
+            })?;
+        }
+        Ok(Transition::default())
+    }
+
+    /// The image flattened with the overlays drawn over it, or `None` without overlays.
+    fn with_overlays(
+        &self,
+        viewport: (u32, u32),
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        caption: &Caption,
+    ) -> Option<DisplayImage> {
+        let overlays = self.overlays.lock().unwrap();
+        if overlays.is_empty() {
+            return None;
+        }
+
+        let mut frame = composite(image, content_mode, viewport);
+        draw_overlays(&mut frame, overlays.values(), caption);
+        let info = image.info();
+        Some(DisplayImage::new(frame, info.size, info.format))
+    }
+
+    /// Renders the image with the overlays drawn over it, if there are any.
+    fn render_frame(
+        &self,
+        backend: &mut Box<dyn DisplayBackend>,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        caption: &Caption,
+        transition: Transition,
+        timeout: i32,
+    ) -> Result<(), PipelineError> {
+        match self.with_overlays(backend.viewport(), image, content_mode, caption) {
+            Some(frame) => {
+                backend.render_image(&frame, ContentMode::ScaleToFill, transition, timeout)
+            }
+            None => backend.render_image(image, content_mode, transition, timeout),
+        }
+    }
+
+    /// Renders the current image again, e.g. after the overlays changed.
+    pub fn refresh(&self) -> Result<(), PipelineError> {
+        let current = self.current.lock().unwrap();
+        match &*current {
+            // Animated images pick up the overlays with their next frame
+            Some((image, content_mode)) if !image.is_animated() => self.render_frame(
+                &mut self.lock(),
+                image,
+                *content_mode,
+                image.caption(),
+                Transition::default(),
+                RENDER_TIMEOUT,
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn overlays(&self) -> BTreeMap<String, Overlay> {
+        self.overlays.lock().unwrap().clone()
+    }
+
+    pub fn set_overlay(&self, name: &str, overlay: Overlay) -> Result<(), PipelineError> {
+        self.overlays
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), overlay);
+        self.refresh()
+    }
+
+    pub fn remove_overlay(&self, name: &str) -> Result<Option<Overlay>, PipelineError> {
+        let overlay = self.overlays.lock().unwrap().remove(name);
+        if overlay.is_some() {
+            self.refresh()?;
+        }
+        Ok(overlay)
+    }
+
+    pub fn clear_overlays(&self) -> Result<(), PipelineError> {
+        self.overlays.lock().unwrap().clear();
+        self.refresh()
+    }
+
+    /// Redraws the image at the start of every minute while a clock overlay is shown.
+    pub async fn run_clock(self) {
+        loop {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let wait = 60_000 - (now.as_millis() % 60_000) as u64;
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+
+            if !self
+                .overlays
+                .lock()
+                .unwrap()
+                .values()
+                .any(Overlay::is_clock)
+            {
+                continue;
+            }
+            let backend = self.clone();
+            let result = tokio::task::spawn_blocking(move || backend.refresh()).await;
+            if let Ok(Err(err)) = result {
+                log::error!("Failed to redraw clock: {:?}", err);
+            }
+        }
+    }
+
+    fn animate(
+        &self,
+        generation: usize,
+        frames: Arc<Vec<AnimationFrame>>,
+        content_mode: ContentMode,
+        caption: Caption,
+        timeout: i32,
+    ) {
+        let mut index = 0;
+        loop {
+            std::thread::sleep(frames[index].delay);
+            index = (index + 1) % frames.len();
+
+            let mut backend = self.lock();
+            if self.generation.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            let frame = &frames[index].image;
+            if let Err(err) = self.render_frame(
+                &mut backend,
+                frame,
+                content_mode,
+                &caption,
+                Transition::default(),
+                timeout,
+            ) {
+                log::error!("Failed to render animation frame: {:?}", err);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::*;
+    use super::*;
+
+    #[test]
+    fn lock_survives_panic_while_rendering() {
+        let backend = Backend::new(MockBackend::new(64, 64));
+        let poisoned = backend.clone();
+        let result = std::thread::spawn(move || {
+            let _guard = poisoned.lock();
+            panic!("render failed");
+        })
+        .join();
+        assert!(result.is_err());
+        assert_eq!(backend.lock().viewport(), (64, 64));
+    }
+}
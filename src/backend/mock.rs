@@ -0,0 +1,160 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::sync::{Arc, Mutex};
+
+use crate::backend::DisplayBackend;
+use crate::display::{frame::*, image::*, power::*, region::*, transition::*};
+use crate::error::{Operation, PipelineError};
+use image::RgbaImage;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Init,
+    Destroy,
+    RenderImage {
+        size: (u32, u32),
+        content_mode: ContentMode,
+        transition: TransitionKind,
+    },
+    RenderRegion {
+        name: String,
+        region: Region,
+        size: (u32, u32),
+    },
+    RemoveRegion(String),
+    PowerOn,
+    PowerOff,
+}
+
+/// Records the calls made to it instead of showing anything.
+#[derive(Debug, Default, Clone)]
+pub struct MockBackend {
+    pub viewport: (u32, u32),
+    pub calls: Arc<Mutex<Vec<Call>>>,
+    pub fail: bool,
+    pub unsupported: Vec<TransitionKind>,
+}
+
+impl MockBackend {
+    pub fn new(width: u32, height: u32) -> MockBackend {
+        MockBackend {
+            viewport: (width, height),
+            ..Default::default()
+        }
+    }
+
+    /// A backend whose rendering always fails.
+    pub fn failing(width: u32, height: u32) -> MockBackend {
+        MockBackend {
+            fail: true,
+            ..MockBackend::new(width, height)
+        }
+    }
+
+    /// A backend that cannot play the given transition, like OMX with crossfade.
+    pub fn without_transition(width: u32, height: u32, kind: TransitionKind) -> MockBackend {
+        MockBackend {
+            unsupported: vec![kind],
+            ..MockBackend::new(width, height)
+        }
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: Call) -> Result<(), PipelineError> {
+        self.calls.lock().unwrap().push(call);
+        match self.fail {
+            true => Err(PipelineError::Assertion(Operation::WriteFrameFailed)),
+            false => Ok(()),
+        }
+    }
+}
+
+impl DisplayBackend for MockBackend {
+    fn init(&mut self) -> Result<(), PipelineError> {
+        self.calls.lock().unwrap().push(Call::Init);
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), PipelineError> {
+        self.calls.lock().unwrap().push(Call::Destroy);
+        Ok(())
+    }
+
+    fn render_image(
+        &mut self,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        transition: Transition,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        self.record(Call::RenderImage {
+            size: image.size(),
+            content_mode,
+            transition: transition.kind,
+        })
+    }
+
+    fn render_region(
+        &mut self,
+        name: &str,
+        region: Region,
+        image: &DisplayImage,
+        _content_mode: ContentMode,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        self.record(Call::RenderRegion {
+            name: name.to_string(),
+            region,
+            size: image.size(),
+        })
+    }
+
+    fn remove_region(&mut self, name: &str) -> Result<(), PipelineError> {
+        self.record(Call::RemoveRegion(name.to_string()))
+    }
+
+    fn power_on(&mut self) -> Result<(), PipelineError> {
+        self.record(Call::PowerOn)
+    }
+
+    fn power_off(&mut self) -> Result<(), PipelineError> {
+        self.record(Call::PowerOff)
+    }
+
+    fn display_info(&mut self) -> Result<DisplayInfo, PipelineError> {
+        if self.fail {
+            return Err(PipelineError::Assertion(Operation::WriteFrameFailed));
+        }
+        // On until the last power call turned it off
+        let power = self.calls().iter().rev().find_map(|call| match call {
+            Call::PowerOn => Some(true),
+            Call::PowerOff => Some(false),
+            _ => None,
+        });
+        let (width, height) = self.viewport;
+        Ok(DisplayInfo {
+            attached: true,
+            power: power.unwrap_or(true),
+            width,
+            height,
+            ..Default::default()
+        })
+    }
+
+    fn screenshot(&mut self) -> Result<RgbaImage, PipelineError> {
+        Ok(black_frame(self.viewport))
+    }
+
+    fn viewport(&self) -> (u32, u32) {
+        self.viewport
+    }
+
+    fn supports_transition(&self, kind: TransitionKind) -> bool {
+        !self.unsupported.contains(&kind)
+    }
+}
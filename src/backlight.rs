@@ -0,0 +1,249 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use gotham_derive::*;
+
+use crate::display::backlight::*;
+
+const BRIGHTNESS_FILE: &str = "brightness";
+const MAX_BRIGHTNESS_FILE: &str = "max_brightness";
+const FADE_STEP: Duration = Duration::from_millis(20);
+pub const DEFAULT_FADE_DURATION: Duration = Duration::from_millis(500);
+
+/// Brightness control of a sysfs backlight device, e.g. the official DSI touchscreen.
+#[derive(Debug, Default, Clone, StateData)]
+pub struct Backlight {
+    device: Option<PathBuf>,
+    generation: Arc<AtomicUsize>,
+}
+
+impl Backlight {
+    /// Uses the device `name` under `root`, or the first one with a brightness control.
+    pub fn discover(root: &Path, name: Option<&str>) -> io::Result<Backlight> {
+        let is_backlight = |path: &Path| {
+            path.join(BRIGHTNESS_FILE).is_file() && path.join(MAX_BRIGHTNESS_FILE).is_file()
+        };
+        let device = match name {
+            Some(name) => {
+                let path = root.join(name);
+                match is_backlight(&path) {
+                    true => Some(path),
+                    false => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no backlight device at {:?}", path),
+                        ))
+                    }
+                }
+            }
+            None => {
+                let entries = match fs::read_dir(root) {
+                    Ok(entries) => entries,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        return Ok(Backlight::default())
+                    }
+                    Err(err) => return Err(err),
+                };
+                let mut devices: Vec<PathBuf> = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| is_backlight(path))
+                    .collect();
+                devices.sort();
+                devices.into_iter().next()
+            }
+        };
+
+        Ok(Backlight {
+            device,
+            ..Default::default()
+        })
+    }
+
+    pub fn device(&self) -> Option<&Path> {
+        self.device.as_deref()
+    }
+
+    fn read(device: &Path, file: &str) -> io::Result<u32> {
+        fs::read_to_string(device.join(file))?
+            .trim()
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn write(device: &Path, brightness: u32) -> io::Result<()> {
+        fs::write(device.join(BRIGHTNESS_FILE), brightness.to_string())
+    }
+
+    fn info_of(device: &Path, brightness: u32) -> io::Result<BacklightInfo> {
+        let name = device
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let max_brightness = Self::read(device, MAX_BRIGHTNESS_FILE)?;
+        Ok(BacklightInfo::new(name, brightness, max_brightness))
+    }
+
+    /// Current brightness, or `None` without a backlight device.
+    pub fn info(&self) -> Option<io::Result<BacklightInfo>> {
+        let device = self.device.as_deref()?;
+        Some(
+            Self::read(device, BRIGHTNESS_FILE)
+                .and_then(|brightness| Self::info_of(device, brightness)),
+        )
+    }
+
+    /// Fades to `level` percent over `duration`, cancelling a fade in progress.
+    pub fn fade(&self, level: u32, duration: Duration) -> Option<io::Result<BacklightInfo>> {
+        let device = self.device.clone()?;
+        Some(self.start_fade(device, level.min(100), duration))
+    }
+
+    fn start_fade(
+        &self,
+        device: PathBuf,
+        level: u32,
+        duration: Duration,
+    ) -> io::Result<BacklightInfo> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let from = Self::read(&device, BRIGHTNESS_FILE)? as i64;
+        let max_brightness = Self::read(&device, MAX_BRIGHTNESS_FILE)?;
+        let to = (level as i64 * max_brightness as i64 + 50) / 100;
+
+        let steps = ((duration.as_millis() / FADE_STEP.as_millis()) as i64)
+            .min((to - from).abs())
+            .max(1);
+        let value = move |step: i64| (from + (to - from) * step / steps) as u32;
+        // Write the first step here, so that permission errors reach the caller
+        Self::write(&device, value(1))?;
+
+        let info = Self::info_of(&device, to as u32)?;
+        let current = self.generation.clone();
+        tokio::spawn(async move {
+            for step in 2..=steps {
+                tokio::time::sleep(FADE_STEP).await;
+                if current.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                if let Err(err) = Self::write(&device, value(step)) {
+                    log::error!("Failed to set brightness: {:?}", err);
+                    return;
+                }
+            }
+        });
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn device(root: &Path, name: &str, brightness: u32, max_brightness: u32) -> PathBuf {
+        let path = root.join(name);
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join(BRIGHTNESS_FILE), format!("{}\n", brightness)).unwrap();
+        fs::write(
+            path.join(MAX_BRIGHTNESS_FILE),
+            format!("{}\n", max_brightness),
+        )
+        .unwrap();
+        path
+    }
+
+    fn brightness(device: &Path) -> u32 {
+        Backlight::read(device, BRIGHTNESS_FILE).unwrap()
+    }
+
+    #[test]
+    fn discovers_first_device() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("a_not_backlight")).unwrap();
+        device(root.path(), "rpi_backlight", 0, 255);
+        device(root.path(), "z_backlight", 0, 255);
+
+        let backlight = Backlight::discover(root.path(), None).unwrap();
+        assert_eq!(
+            backlight.device(),
+            Some(root.path().join("rpi_backlight").as_path())
+        );
+        let backlight = Backlight::discover(root.path(), Some("z_backlight")).unwrap();
+        assert_eq!(
+            backlight.device(),
+            Some(root.path().join("z_backlight").as_path())
+        );
+        assert!(Backlight::discover(root.path(), Some("a_not_backlight")).is_err());
+
+        let missing = root.path().join("missing");
+        assert!(Backlight::discover(&missing, None)
+            .unwrap()
+            .device()
+            .is_none());
+    }
+
+    #[test]
+    fn scales_to_max_brightness() {
+        let root = TempDir::new().unwrap();
+        device(root.path(), "dsi", 128, 255);
+        let backlight = Backlight::discover(root.path(), None).unwrap();
+        let info = backlight.info().unwrap().unwrap();
+        assert_eq!(
+            (info.device.as_str(), info.brightness, info.level),
+            ("dsi", 128, 50)
+        );
+
+        let info = BacklightInfo::new("large".to_string(), 3_000_000_000, 4_000_000_000);
+        assert_eq!(info.level, 75);
+    }
+
+    #[tokio::test]
+    async fn fades_in_steps() {
+        let root = TempDir::new().unwrap();
+        let path = device(root.path(), "dsi", 0, 255);
+        let backlight = Backlight::discover(root.path(), None).unwrap();
+
+        // 100ms are 5 steps of 20ms: 51, 102, 153, 204, 255
+        let info = backlight
+            .fade(100, Duration::from_millis(100))
+            .unwrap()
+            .unwrap();
+        assert_eq!((info.brightness, info.level), (255, 100));
+        assert_eq!(brightness(&path), 51);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(brightness(&path), 255);
+
+        // No more steps than brightness values in between: 254, 253, 252
+        backlight
+            .fade(99, Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        assert_eq!(brightness(&path), 254);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(brightness(&path), 252);
+    }
+
+    #[tokio::test]
+    async fn new_fade_cancels_previous() {
+        let root = TempDir::new().unwrap();
+        let path = device(root.path(), "dsi", 0, 1000);
+        let backlight = Backlight::discover(root.path(), None).unwrap();
+
+        backlight
+            .fade(100, Duration::from_secs(10))
+            .unwrap()
+            .unwrap();
+        backlight
+            .fade(10, Duration::from_millis(0))
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(brightness(&path), 100);
+    }
+}
@@ -0,0 +1,189 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+#[cfg(target_os = "linux")]
+pub mod device;
+
+use std::io;
+
+use crate::display::cec::*;
+
+pub const TV: u8 = 0;
+pub const BROADCAST: u8 = 0xf;
+
+const IMAGE_VIEW_ON: u8 = 0x04;
+const STANDBY: u8 = 0x36;
+const ACTIVE_SOURCE: u8 = 0x82;
+
+/// Sends CEC frames; implemented by the kernel CEC device and by fakes.
+pub trait CecTransport: Send {
+    /// Logical address of this device, claiming one if needed.
+    fn logical_address(&mut self) -> io::Result<u8>;
+
+    fn physical_address(&mut self) -> io::Result<u16>;
+
+    /// Sends `message` (opcode and operands, or nothing for a poll) and returns whether it was acknowledged.
+    fn transmit(&mut self, destination: u8, message: &[u8]) -> io::Result<bool>;
+}
+
+pub struct Cec {
+    transport: Box<dyn CecTransport>,
+}
+
+// io::Error::other needs Rust 1.74
+#[allow(clippy::io_other_error)]
+fn not_acknowledged(destination: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("CEC device {} did not acknowledge", destination),
+    )
+}
+
+impl Cec {
+    pub fn new<T: CecTransport + 'static>(transport: T) -> Cec {
+        Cec {
+            transport: Box::new(transport),
+        }
+    }
+
+    fn send(&mut self, destination: u8, message: &[u8]) -> io::Result<()> {
+        // Broadcast messages are never acknowledged
+        match self.transport.transmit(destination, message)? || destination == BROADCAST {
+            true => Ok(()),
+            false => Err(not_acknowledged(destination)),
+        }
+    }
+
+    /// Wakes the TV and switches it to this input.
+    pub fn wake(&mut self) -> io::Result<()> {
+        self.send(TV, &[IMAGE_VIEW_ON])?;
+        let [high, low] = self.transport.physical_address()?.to_be_bytes();
+        self.send(BROADCAST, &[ACTIVE_SOURCE, high, low])
+    }
+
+    pub fn standby(&mut self) -> io::Result<()> {
+        self.send(TV, &[STANDBY])
+    }
+
+    pub fn topology(&mut self) -> io::Result<CecTopology> {
+        let logical_address = self.transport.logical_address()?;
+        let physical_address = self.transport.physical_address()?;
+        let mut devices = Vec::new();
+        for address in (0..BROADCAST).filter(|address| *address != logical_address) {
+            if self.transport.transmit(address, &[])? {
+                devices.push(CecDevice::new(address));
+            }
+        }
+
+        Ok(CecTopology {
+            physical_address,
+            logical_address,
+            devices,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Destination and message of a sent frame.
+    type Frame = (u8, Vec<u8>);
+
+    /// Records the frames sent, acknowledging those to the devices in `present`.
+    #[derive(Default, Clone)]
+    struct FakeTransport {
+        logical_address: u8,
+        physical_address: u16,
+        present: Vec<u8>,
+        sent: Arc<Mutex<Vec<Frame>>>,
+    }
+
+    impl CecTransport for FakeTransport {
+        fn logical_address(&mut self) -> io::Result<u8> {
+            Ok(self.logical_address)
+        }
+
+        fn physical_address(&mut self) -> io::Result<u16> {
+            Ok(self.physical_address)
+        }
+
+        fn transmit(&mut self, destination: u8, message: &[u8]) -> io::Result<bool> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((destination, message.to_vec()));
+            Ok(self.present.contains(&destination))
+        }
+    }
+
+    fn fake(present: Vec<u8>) -> (Cec, FakeTransport) {
+        let transport = FakeTransport {
+            logical_address: 4,
+            physical_address: 0x1200,
+            present,
+            ..Default::default()
+        };
+        (Cec::new(transport.clone()), transport)
+    }
+
+    fn sent(transport: &FakeTransport) -> Vec<Frame> {
+        transport.sent.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn wakes_tv_and_claims_active_source() {
+        let (mut cec, transport) = fake(vec![TV]);
+        cec.wake().unwrap();
+        assert_eq!(
+            sent(&transport),
+            vec![
+                (TV, vec![IMAGE_VIEW_ON]),
+                (BROADCAST, vec![ACTIVE_SOURCE, 0x12, 0x00]),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_without_tv() {
+        let (mut cec, transport) = fake(vec![]);
+        assert!(cec.wake().is_err());
+        assert!(cec.standby().is_err());
+        // Active Source is not sent when the TV did not answer Image View On
+        assert_eq!(
+            sent(&transport),
+            vec![(TV, vec![IMAGE_VIEW_ON]), (TV, vec![STANDBY])]
+        );
+    }
+
+    #[test]
+    fn puts_tv_into_standby() {
+        let (mut cec, transport) = fake(vec![TV]);
+        cec.standby().unwrap();
+        assert_eq!(sent(&transport), vec![(TV, vec![STANDBY])]);
+    }
+
+    #[test]
+    fn polls_other_devices() {
+        let (mut cec, transport) = fake(vec![TV, 5]);
+        let topology = cec.topology().unwrap();
+        assert_eq!(topology.logical_address, 4);
+        assert_eq!(topology.physical_address, 0x1200);
+        assert_eq!(
+            topology.devices,
+            vec![CecDevice::new(TV), CecDevice::new(5)]
+        );
+
+        let polled: Vec<u8> = sent(&transport)
+            .into_iter()
+            .map(|(destination, message)| {
+                assert!(message.is_empty());
+                destination
+            })
+            .collect();
+        let expected: Vec<u8> = (0..BROADCAST).filter(|address| *address != 4).collect();
+        assert_eq!(polled, expected);
+    }
+}
@@ -0,0 +1,131 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::cec::CecTransport;
+
+const CEC_ADAP_G_PHYS_ADDR: u32 = 0x8002_6101;
+const CEC_ADAP_G_LOG_ADDRS: u32 = 0x805c_6103;
+const CEC_ADAP_S_LOG_ADDRS: u32 = 0xc05c_6104;
+const CEC_TRANSMIT: u32 = 0xc038_6105;
+
+const CEC_TX_STATUS_OK: u8 = 1 << 0;
+const CEC_LOG_ADDR_INVALID: u8 = 0xff;
+const CEC_OP_CEC_VERSION_1_4: u8 = 5;
+const CEC_OP_PRIM_DEVTYPE_PLAYBACK: u8 = 4;
+const CEC_LOG_ADDR_TYPE_PLAYBACK: u8 = 3;
+const CEC_OP_ALL_DEVTYPE_PLAYBACK: u8 = 0x10;
+const CEC_VENDOR_ID_NONE: u32 = 0xffff_ffff;
+const OSD_NAME: &[u8] = b"dpf-pi";
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct CecMsg {
+    tx_ts: u64,
+    rx_ts: u64,
+    len: u32,
+    timeout: u32,
+    sequence: u32,
+    flags: u32,
+    msg: [u8; 16],
+    reply: u8,
+    rx_status: u8,
+    tx_status: u8,
+    tx_arb_lost_cnt: u8,
+    tx_nack_cnt: u8,
+    tx_low_drive_cnt: u8,
+    tx_error_cnt: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct CecLogAddrs {
+    log_addr: [u8; 4],
+    log_addr_mask: u16,
+    cec_version: u8,
+    num_log_addrs: u8,
+    vendor_id: u32,
+    flags: u32,
+    osd_name: [u8; 15],
+    primary_device_type: [u8; 4],
+    log_addr_type: [u8; 4],
+    all_device_types: [u8; 4],
+    features: [[u8; 12]; 4],
+}
+
+fn ioctl<T>(file: &File, request: u32, arg: &mut T) -> io::Result<()> {
+    match unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Linux CEC adapter such as `/dev/cec0`, used as a playback device.
+pub struct CecDeviceTransport {
+    file: File,
+}
+
+impl CecDeviceTransport {
+    pub fn open(path: &Path) -> io::Result<CecDeviceTransport> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(CecDeviceTransport { file })
+    }
+
+    fn claim(&self) -> io::Result<()> {
+        let mut log_addrs = CecLogAddrs {
+            cec_version: CEC_OP_CEC_VERSION_1_4,
+            num_log_addrs: 1,
+            vendor_id: CEC_VENDOR_ID_NONE,
+            ..Default::default()
+        };
+        log_addrs.osd_name[..OSD_NAME.len()].copy_from_slice(OSD_NAME);
+        log_addrs.primary_device_type[0] = CEC_OP_PRIM_DEVTYPE_PLAYBACK;
+        log_addrs.log_addr_type[0] = CEC_LOG_ADDR_TYPE_PLAYBACK;
+        log_addrs.all_device_types[0] = CEC_OP_ALL_DEVTYPE_PLAYBACK;
+        // Blocks until the address is claimed
+        ioctl(&self.file, CEC_ADAP_S_LOG_ADDRS, &mut log_addrs)
+    }
+
+    fn configured_address(&self) -> io::Result<Option<u8>> {
+        let mut log_addrs = CecLogAddrs::default();
+        ioctl(&self.file, CEC_ADAP_G_LOG_ADDRS, &mut log_addrs)?;
+        Ok(match log_addrs.num_log_addrs {
+            0 => None,
+            _ => Some(log_addrs.log_addr[0]).filter(|address| *address != CEC_LOG_ADDR_INVALID),
+        })
+    }
+}
+
+impl CecTransport for CecDeviceTransport {
+    fn logical_address(&mut self) -> io::Result<u8> {
+        if let Some(address) = self.configured_address()? {
+            return Ok(address);
+        }
+        self.claim()?;
+        self.configured_address()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "no CEC logical address"))
+    }
+
+    fn physical_address(&mut self) -> io::Result<u16> {
+        let mut address = 0u16;
+        ioctl(&self.file, CEC_ADAP_G_PHYS_ADDR, &mut address)?;
+        Ok(address)
+    }
+
+    fn transmit(&mut self, destination: u8, message: &[u8]) -> io::Result<bool> {
+        let initiator = self.logical_address()?;
+        let mut msg = CecMsg {
+            len: 1 + message.len() as u32,
+            ..Default::default()
+        };
+        msg.msg[0] = (initiator << 4) | (destination & 0xf);
+        msg.msg[1..=message.len()].copy_from_slice(message);
+        ioctl(&self.file, CEC_TRANSMIT, &mut msg)?;
+        Ok(msg.tx_status & CEC_TX_STATUS_OK != 0)
+    }
+}
@@ -91,6 +91,9 @@ impl Component {
         &mut self,
         direction: Direction,
         display_rect: Option<OMX_DISPLAYRECTTYPE>,
+        transform: OMX_DISPLAYTRANSFORMTYPE,
+        alpha: u8,
+        layer: i32,
     ) -> Result<(), PipelineError> {
         let port = match direction {
             Direction::In => self.in_port,
@@ -108,7 +111,9 @@ impl Component {
                 | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_NOASPECT
                 | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_FULLSCREEN
                 | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_DEST_RECT
-                | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_TRANSFORM,
+                | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_TRANSFORM
+                | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_ALPHA
+                | OMX_DISPLAYSETTYPE_OMX_DISPLAY_SET_LAYER,
             num: 0,
             mode: OMX_DISPLAYMODETYPE_OMX_DISPLAY_MODE_LETTERBOX,
             noaspect: OMX_BOOL_OMX_TRUE,
@@ -117,7 +122,9 @@ impl Component {
                 _ => OMX_BOOL_OMX_FALSE,
             },
             dest_rect: display_rect.unwrap_or_default(),
-            transform: OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_ROT0,
+            transform,
+            alpha: alpha as u32,
+            layer,
             ..Default::default()
         };
         self.set_config(OMX_INDEXTYPE_OMX_IndexConfigDisplayRegion, &mut disp)
@@ -0,0 +1,168 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::display::{image::*, transition::*};
+use crate::slideshow::*;
+
+pub fn scan(
+    dir: &Path,
+    slideshow: &Slideshow,
+    content_mode: ContentMode,
+    transition: Transition,
+) -> io::Result<usize> {
+    let mut paths = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| slideshow.add_file(path, content_mode, transition))
+        .count())
+}
+
+#[cfg(target_os = "linux")]
+pub async fn watch(
+    dir: PathBuf,
+    slideshow: Slideshow,
+    content_mode: ContentMode,
+    transition: Transition,
+) -> io::Result<()> {
+    use futures::prelude::*;
+    use inotify::{EventMask, Inotify, WatchMask};
+
+    let mut inotify = Inotify::init()?;
+    inotify.add_watch(
+        &dir,
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE | WatchMask::MOVED_FROM,
+    )?;
+
+    let mut events = inotify.event_stream([0u8; 4096])?;
+    while let Some(event) = events.next().await {
+        let event = event?;
+        let path = match event.name {
+            Some(name) => dir.join(name),
+            None => continue,
+        };
+
+        if slideshow.remove_file(&path).is_some() {
+            log::info!("Removed {:?} from slideshow", path);
+        }
+        if event
+            .mask
+            .intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO)
+            && slideshow
+                .add_file(path.clone(), content_mode, transition)
+                .is_some()
+        {
+            log::info!("Added {:?} to slideshow", path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn watch(
+    dir: PathBuf,
+    _slideshow: Slideshow,
+    _content_mode: ContentMode,
+    _transition: Transition,
+) -> io::Result<()> {
+    log::warn!("Watching {:?} is not supported on this platform", dir);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{mock::*, Backend};
+    use crate::store::ImageStore;
+    use image::RgbaImage;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn slideshow() -> Slideshow {
+        let backend = Backend::new(MockBackend::new(64, 64));
+        Slideshow::new(backend, ImageStore::new(None), 10)
+    }
+
+    fn names(slideshow: &Slideshow) -> Vec<String> {
+        slideshow
+            .lock()
+            .slides
+            .iter()
+            .filter_map(|slide| slide.path.as_ref()?.file_name()?.to_str().map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn scans_images_in_order() {
+        let dir = TempDir::new().unwrap();
+        for name in ["b.png", "c.jpg", "a.png"] {
+            RgbaImage::new(4, 4).save(dir.path().join(name)).unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        std::fs::create_dir(dir.path().join("d.png")).unwrap();
+
+        let slideshow = slideshow();
+        let count = scan(
+            dir.path(),
+            &slideshow,
+            ContentMode::None,
+            Transition::default(),
+        )
+        .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(names(&slideshow), vec!["a.png", "b.png", "c.jpg"]);
+
+        // Files already in the slideshow are not added twice
+        let count = scan(
+            dir.path(),
+            &slideshow,
+            ContentMode::None,
+            Transition::default(),
+        )
+        .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn watches_for_new_images() {
+        let dir = TempDir::new().unwrap();
+        RgbaImage::new(4, 4).save(dir.path().join("b.png")).unwrap();
+        let slideshow = slideshow();
+        scan(
+            dir.path(),
+            &slideshow,
+            ContentMode::None,
+            Transition::default(),
+        )
+        .unwrap();
+
+        tokio::spawn(watch(
+            dir.path().to_path_buf(),
+            slideshow.clone(),
+            ContentMode::None,
+            Transition::default(),
+        ));
+        // Let the watch start before changing the directory
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        RgbaImage::new(4, 4).save(dir.path().join("a.png")).unwrap();
+        std::fs::remove_file(dir.path().join("b.png")).unwrap();
+
+        for _ in 0..50 {
+            if names(&slideshow) == vec!["a.png"] {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("slideshow has {:?}", names(&slideshow));
+    }
+}
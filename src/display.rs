@@ -2,7 +2,16 @@
 Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
+pub mod background;
+pub mod backlight;
+pub mod cec;
+pub mod frame;
 pub mod image;
+pub mod orientation;
+pub mod overlay;
+pub mod playlist;
 pub mod power;
 pub mod rect;
+pub mod region;
 pub mod result;
+pub mod transition;
@@ -0,0 +1,75 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+/// What fills the viewport around the image.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Background {
+    #[default]
+    None,
+    Color(Rgba<u8>),
+    Blur,
+}
+
+impl Background {
+    pub fn from_str(background: &str) -> Self {
+        let hex = background.trim_start_matches('#');
+        match background {
+            "blur" => Background::Blur,
+            // from_str_radix alone would accept a leading '+'
+            _ if hex.len() == 6 && hex.bytes().all(|c| c.is_ascii_hexdigit()) => {
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) => {
+                        let [_, r, g, b] = rgb.to_be_bytes();
+                        Background::Color(Rgba([r, g, b, 0xff]))
+                    }
+                    Err(_) => Background::None,
+                }
+            }
+            _ => Background::None,
+        }
+    }
+}
+
+impl Serialize for Background {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Background::None => s.serialize_str("none"),
+            Background::Color(Rgba([r, g, b, _])) => {
+                s.serialize_str(&format!("#{:02x}{:02x}{:02x}", r, g, b))
+            }
+            Background::Blur => s.serialize_str("blur"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Background {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(d).map(|background| Background::from_str(&background))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_color() {
+        assert_eq!(
+            Background::from_str("#20a0ff"),
+            Background::Color(Rgba([0x20, 0xa0, 0xff, 0xff]))
+        );
+        assert_eq!(Background::from_str("blur"), Background::Blur);
+        assert_eq!(Background::from_str("#+20a0f"), Background::None);
+        assert_eq!(Background::from_str("#20a0f"), Background::None);
+    }
+}
@@ -0,0 +1,50 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use gotham::handler::IntoResponse;
+use gotham::hyper::{Body, Response, StatusCode};
+use gotham::state::State;
+use serde::Serialize;
+
+use crate::display::result::*;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacklightInfo {
+    pub device: String,
+    pub brightness: u32,
+    pub max_brightness: u32,
+    /// Brightness in percent of `max_brightness`.
+    pub level: u32,
+}
+
+impl BacklightInfo {
+    pub fn new(device: String, brightness: u32, max_brightness: u32) -> Self {
+        BacklightInfo {
+            device,
+            brightness,
+            max_brightness,
+            level: match max_brightness {
+                0 => 0,
+                max => {
+                    let (brightness, max) = (brightness as u64, max as u64);
+                    ((brightness * 100 + max / 2) / max) as u32
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct BrightnessResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backlight: Option<BacklightInfo>,
+}
+
+impl IntoResponse for BrightnessResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
@@ -0,0 +1,71 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use gotham::handler::IntoResponse;
+use gotham::hyper::{Body, Response, StatusCode};
+use gotham::state::State;
+use serde::Serialize;
+
+use crate::display::result::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CecDevice {
+    pub logical_address: u8,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+impl CecDevice {
+    pub fn new(logical_address: u8) -> Self {
+        let kind = match logical_address {
+            0 => "tv",
+            1 | 2 | 9 => "recording",
+            3 | 6 | 7 | 10 => "tuner",
+            4 | 8 | 11 => "playback",
+            5 => "audio_system",
+            14 => "specific",
+            _ => "reserved",
+        };
+        CecDevice {
+            logical_address,
+            kind,
+        }
+    }
+}
+
+/// This device and the other devices that answered a poll on the CEC bus.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CecTopology {
+    #[serde(serialize_with = "physical_address_serde")]
+    pub physical_address: u16,
+    pub logical_address: u8,
+    pub devices: Vec<CecDevice>,
+}
+
+fn physical_address_serde<S>(address: &u16, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    s.serialize_str(&format!(
+        "{}.{}.{}.{}",
+        address >> 12,
+        (address >> 8) & 0xf,
+        (address >> 4) & 0xf,
+        address & 0xf
+    ))
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct CecResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cec: Option<CecTopology>,
+}
+
+impl IntoResponse for CecResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
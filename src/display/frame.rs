@@ -0,0 +1,146 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::borrow::Cow;
+
+use image::imageops::{self, FilterType};
+use image::{Pixel, Rgba, RgbaImage};
+
+#[cfg(any(feature = "software", feature = "fbdev"))]
+use crate::display::region::Region;
+use crate::display::{background::*, image::*, orientation::*, rect::*};
+
+// The blurred fill is built at this fraction of the viewport and scaled up
+const BLUR_DOWNSCALE: u32 = 8;
+const BLUR_SIGMA: f32 = 3f32;
+
+pub fn black_frame(viewport: (u32, u32)) -> RgbaImage {
+    let (vw, vh) = viewport;
+    RgbaImage::from_pixel(vw, vh, Rgba([0, 0, 0, 0xff]))
+}
+
+/// Fills the viewport with `background`, using `source` for the blurred fill.
+pub fn background_frame(
+    background: Background,
+    viewport: (u32, u32),
+    source: &RgbaImage,
+) -> RgbaImage {
+    let (vw, vh) = viewport;
+    match background {
+        Background::None => black_frame(viewport),
+        Background::Color(color) => RgbaImage::from_pixel(vw, vh, color),
+        Background::Blur => {
+            let small = ((vw / BLUR_DOWNSCALE).max(1), (vh / BLUR_DOWNSCALE).max(1));
+            let DisplayRect { x, y, w, h } = DisplayRect::new_with_mode(
+                ContentMode::Aspect(AspectMode::Fill),
+                small,
+                source.dimensions(),
+                Orientation::default(),
+                Focus::default(),
+            );
+            let fill = imageops::resize(source, w as u32, h as u32, FilterType::Triangle);
+            let mut frame = black_frame(small);
+            imageops::overlay(
+                &mut frame,
+                &imageops::blur(&fill, BLUR_SIGMA),
+                x as i64,
+                y as i64,
+            );
+            imageops::resize(&frame, vw, vh, FilterType::Triangle)
+        }
+    }
+}
+
+pub fn effective_viewport(viewport: (u32, u32), rotation: u32) -> (u32, u32) {
+    Orientation::new(rotation, false).size(viewport)
+}
+
+pub fn rotate_frame(frame: &RgbaImage, rotation: u32) -> Cow<'_, RgbaImage> {
+    match rotation {
+        0 => Cow::Borrowed(frame),
+        _ => Cow::Owned(Orientation::new(rotation, false).apply(frame)),
+    }
+}
+
+pub fn composite(
+    image: &DisplayImage,
+    content_mode: ContentMode,
+    viewport: (u32, u32),
+) -> RgbaImage {
+    let (vw, vh) = viewport;
+    let orientation = image.orientation();
+    let DisplayRect { x, y, w, h } = DisplayRect::new_with_mode(
+        content_mode,
+        viewport,
+        image.size(),
+        orientation,
+        image.focus(),
+    );
+    if w <= 0 || h <= 0 {
+        return black_frame(viewport);
+    }
+
+    let source = orientation.apply(&image.to_rgba_image());
+    // Skip the blurred fill when the image leaves no bars
+    let covered = x <= 0 && y <= 0 && x + w >= vw as i16 && y + h >= vh as i16;
+    let background = match image.background() {
+        Background::Blur if covered => Background::None,
+        background => background,
+    };
+    let mut frame = background_frame(background, viewport, &source);
+
+    let source = if source.dimensions() == (w as u32, h as u32) {
+        source
+    } else {
+        imageops::resize(&source, w as u32, h as u32, FilterType::Triangle)
+    };
+
+    for (sx, sy, pixel) in source.enumerate_pixels() {
+        let (dx, dy) = (x as i32 + sx as i32, y as i32 + sy as i32);
+        if dx < 0 || dy < 0 || dx >= vw as i32 || dy >= vh as i32 {
+            continue;
+        }
+        frame.get_pixel_mut(dx as u32, dy as u32).blend(pixel);
+    }
+
+    frame
+}
+
+/// Returns the image drawn onto its background at the viewport size.
+#[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
+pub fn flatten(
+    image: &DisplayImage,
+    content_mode: ContentMode,
+    viewport: (u32, u32),
+) -> DisplayImage {
+    let info = image.info();
+    DisplayImage::new(
+        composite(image, content_mode, viewport),
+        info.size,
+        info.format,
+    )
+}
+
+/// Draws the contents of regions onto `frame`, lowest layer first.
+#[cfg(any(feature = "software", feature = "fbdev"))]
+pub fn overlay_regions<'a>(
+    frame: &mut RgbaImage,
+    regions: impl Iterator<Item = &'a (Region, RgbaImage)>,
+) {
+    let (vw, vh) = frame.dimensions();
+    let mut regions: Vec<_> = regions.collect();
+    regions.sort_by_key(|(region, _)| region.layer);
+
+    for (region, content) in regions {
+        for (sx, sy, pixel) in content.enumerate_pixels() {
+            let (dx, dy) = (region.x as i32 + sx as i32, region.y as i32 + sy as i32);
+            if dx < 0 || dy < 0 || dx >= vw as i32 || dy >= vh as i32 {
+                continue;
+            }
+            let mut pixel = *pixel;
+            pixel[3] = (pixel[3] as f32 * region.opacity) as u8;
+            frame.get_pixel_mut(dx as u32, dy as u32).blend(&pixel);
+        }
+    }
+}
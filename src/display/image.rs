@@ -2,11 +2,21 @@
 Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
+use std::sync::Arc;
+use std::time::Duration;
 
-use image::{ImageBuffer, ImageFormat, Rgba, RgbaImage};
+use image::{imageops, ImageBuffer, ImageFormat, Rgba, RgbaImage};
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+use crate::display::{background::*, orientation::*, overlay::Caption, rect::*};
+
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: DisplayImage,
+    pub delay: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct DisplayImage {
     #[serde(skip_serializing)]
     image: RgbaImage,
@@ -14,18 +24,77 @@ pub struct DisplayImage {
     height: u32,
     size: usize,
     #[serde(serialize_with = "format_serde")]
-    format: ImageFormat,
+    format: SourceFormat,
+    orientation: Orientation,
+    background: Background,
+    focus: Focus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crop: Option<CropRect>,
+    #[serde(flatten)]
+    caption: Caption,
+    scale: f32,
+    #[serde(skip_serializing)]
+    frames: Arc<Vec<AnimationFrame>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+    #[serde(serialize_with = "format_serde")]
+    pub format: SourceFormat,
+    pub orientation: Orientation,
+    pub background: Background,
+    pub focus: Focus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
+    #[serde(flatten)]
+    pub caption: Caption,
+    pub scale: f32,
+    pub frames: usize,
+    pub duration: u32,
+}
+
+/// Format of the uploaded bytes; HEIF is decoded outside of the `image` crate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SourceFormat {
+    Image(ImageFormat),
+    #[cfg(feature = "heif")]
+    Heif,
 }
 
-fn format_serde<S>(image_format: &ImageFormat, s: S) -> Result<S::Ok, S::Error>
+impl From<ImageFormat> for SourceFormat {
+    fn from(format: ImageFormat) -> Self {
+        SourceFormat::Image(format)
+    }
+}
+
+fn format_serde<S>(format: &SourceFormat, s: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    s.serialize_str(&format!("{:?}", image_format).to_lowercase())
+    match format {
+        SourceFormat::Image(image_format) => {
+            s.serialize_str(&format!("{:?}", image_format).to_lowercase())
+        }
+        #[cfg(feature = "heif")]
+        SourceFormat::Heif => s.serialize_str("heif"),
+    }
+}
+
+pub fn mime_type(format: SourceFormat) -> mime::Mime {
+    let mime_type = match format {
+        SourceFormat::Image(image_format) => image_format.to_mime_type(),
+        #[cfg(feature = "heif")]
+        SourceFormat::Heif => "image/heif",
+    };
+    mime_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM)
 }
 
 impl DisplayImage {
-    pub fn new(img: RgbaImage, size: usize, format: ImageFormat) -> Self {
+    pub fn new(img: RgbaImage, size: usize, format: impl Into<SourceFormat>) -> Self {
+        let format = format.into();
         let width = img.width();
         let height = img.height();
         let xstride = (width + 0b1111) & !0b1111;
@@ -35,6 +104,13 @@ impl DisplayImage {
                 height,
                 size,
                 format,
+                orientation: Orientation::default(),
+                background: Background::default(),
+                focus: Focus::default(),
+                crop: None,
+                caption: Caption::default(),
+                scale: 1f32,
+                frames: Arc::default(),
                 image: img,
             };
         }
@@ -52,10 +128,35 @@ impl DisplayImage {
             height,
             size,
             format,
+            orientation: Orientation::default(),
+            background: Background::default(),
+            focus: Focus::default(),
+            crop: None,
+            caption: Caption::default(),
+            scale: 1f32,
+            frames: Arc::default(),
             image,
         }
     }
 
+    pub fn new_animated(
+        frames: Vec<(RgbaImage, Duration)>,
+        size: usize,
+        format: impl Into<SourceFormat>,
+    ) -> Self {
+        let format = format.into();
+        let frames: Vec<AnimationFrame> = frames
+            .into_iter()
+            .map(|(img, delay)| AnimationFrame {
+                image: Self::new(img, size, format),
+                delay,
+            })
+            .collect();
+        let mut image = frames[0].image.clone();
+        image.frames = Arc::new(frames);
+        image
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -68,22 +169,179 @@ impl DisplayImage {
         (self.width, self.height)
     }
 
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        for frame in Arc::make_mut(&mut self.frames) {
+            frame.image.orientation = orientation;
+        }
+    }
+
+    pub fn background(&self) -> Background {
+        self.background
+    }
+
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+        for frame in Arc::make_mut(&mut self.frames) {
+            frame.image.background = background;
+        }
+    }
+
+    pub fn focus(&self) -> Focus {
+        self.focus
+    }
+
+    pub fn set_focus(&mut self, focus: Focus) {
+        self.focus = focus;
+        for frame in Arc::make_mut(&mut self.frames) {
+            frame.image.focus = focus;
+        }
+    }
+
+    pub fn crop(&self) -> Option<CropRect> {
+        self.crop
+    }
+
+    /// Crops the image to a region of its current orientation.
+    pub fn set_crop(&mut self, crop: CropRect) {
+        // Snap to whole pixels before mapping, so that rounding does not depend on the orientation
+        let (sw, sh) = self.orientation.size(self.size());
+        let snap = |value: f32, size: u32| (value * size as f32).round() / size as f32;
+        let CropRect { x, y, w, h } = CropRect {
+            x: snap(crop.x, sw),
+            y: snap(crop.y, sh),
+            w: snap(crop.w, sw),
+            h: snap(crop.h, sh),
+        }
+        .unorient(self.orientation);
+        let (width, height) = (self.width as f32, self.height as f32);
+        let x = ((x * width).round() as u32).min(self.width - 1);
+        let y = ((y * height).round() as u32).min(self.height - 1);
+        let w = ((w * width).round() as u32).max(1).min(self.width - x);
+        let h = ((h * height).round() as u32).max(1).min(self.height - y);
+
+        let cropped = |image: &DisplayImage| {
+            let pixels = imageops::crop_imm(&image.image, x, y, w, h).to_image();
+            DisplayImage {
+                orientation: image.orientation,
+                background: image.background,
+                focus: image.focus,
+                crop: Some(crop),
+                caption: image.caption.clone(),
+                scale: image.scale,
+                ..Self::new(pixels, image.size, image.format)
+            }
+        };
+        let frames: Vec<AnimationFrame> = self
+            .frames
+            .iter()
+            .map(|frame| AnimationFrame {
+                image: cropped(&frame.image),
+                delay: frame.delay,
+            })
+            .collect();
+        *self = DisplayImage {
+            frames: Arc::new(frames),
+            ..cropped(self)
+        };
+    }
+
+    pub fn caption(&self) -> &Caption {
+        &self.caption
+    }
+
+    pub fn set_caption(&mut self, caption: Caption) {
+        self.caption = caption;
+    }
+
+    /// Replaces the title read from EXIF, if any.
+    pub fn set_title(&mut self, title: Option<String>) {
+        if title.is_some() {
+            self.caption.title = title;
+        }
+    }
+
+    /// Ratio of the decoded size to the size of the original image.
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    pub fn frames(&self) -> Arc<Vec<AnimationFrame>> {
+        self.frames.clone()
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.delay).sum()
+    }
+
+    pub fn info(&self) -> ImageInfo {
+        ImageInfo {
+            width: self.width,
+            height: self.height,
+            size: self.size,
+            format: self.format,
+            orientation: self.orientation,
+            background: self.background,
+            focus: self.focus,
+            crop: self.crop,
+            caption: self.caption.clone(),
+            scale: self.scale,
+            frames: self.frames.len().max(1),
+            duration: self.duration().as_millis() as u32,
+        }
+    }
+
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     pub fn len(&self) -> u32 {
         self.image.len() as u32
     }
 
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     pub fn as_raw(&self) -> &[u8] {
         self.image.as_raw()
     }
+
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        imageops::crop_imm(&self.image, 0, 0, self.width, self.height).to_image()
+    }
+}
+
+/// Placement options requested along with an image.
+#[derive(Debug, Default, Copy, Clone, Serialize)]
+pub struct Layout {
+    pub orientation: Orientation,
+    pub background: Background,
+    pub focus: Focus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
+}
+
+impl Layout {
+    pub fn apply(&self, image: &mut DisplayImage) {
+        image.set_orientation(image.orientation().then(self.orientation));
+        image.set_background(self.background);
+        image.set_focus(self.focus);
+        if let Some(crop) = self.crop {
+            image.set_crop(crop);
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum AspectMode {
     Fill,
     Fit,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ContentMode {
     None,
     Aspect(AspectMode),
@@ -109,7 +367,7 @@ impl ContentMode {
         match mode {
             "AspectFit" | "aspect_fit" => ContentMode::Aspect(AspectMode::Fit),
             "AspectFill" | "aspect_fill" => ContentMode::Aspect(AspectMode::Fill),
-            "Fill" | "fill" => ContentMode::ScaleToFill,
+            "Fill" | "fill" | "scaletofill" => ContentMode::ScaleToFill,
             _ => ContentMode::None,
         }
     }
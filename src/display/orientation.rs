@@ -0,0 +1,239 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use image::{imageops, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// Mirror horizontally (if `mirror`), then rotate clockwise by `rotate` degrees.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Orientation {
+    pub rotate: u32,
+    pub mirror: bool,
+}
+
+impl Orientation {
+    pub fn new(rotate: u32, mirror: bool) -> Self {
+        Orientation {
+            rotate: rotate % 360,
+            mirror,
+        }
+    }
+
+    /// Returns `None` unless `rotate` is a right angle and `flip` is `h` or `v`.
+    pub fn from_str(rotate: Option<u32>, flip: Option<&str>) -> Option<Self> {
+        let rotate = match rotate.unwrap_or(0) {
+            rotate @ (0 | 90 | 180 | 270) => rotate,
+            _ => return None,
+        };
+        let flip = match flip.unwrap_or_default() {
+            "" => Orientation::default(),
+            "h" | "horizontal" => Orientation::new(0, true),
+            "v" | "vertical" => Orientation::new(180, true),
+            _ => return None,
+        };
+        Some(flip.then(Orientation::new(rotate, false)))
+    }
+
+    pub fn from_exif(body: &[u8]) -> Self {
+        let mut cur = std::io::Cursor::new(body);
+        let value = exif::Reader::new()
+            .read_from_container(&mut cur)
+            .ok()
+            .and_then(|exif| {
+                exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                    .and_then(|field| field.value.get_uint(0))
+            });
+        match value {
+            Some(2) => Orientation::new(0, true),
+            Some(3) => Orientation::new(180, false),
+            Some(4) => Orientation::new(180, true),
+            Some(5) => Orientation::new(270, true),
+            Some(6) => Orientation::new(90, false),
+            Some(7) => Orientation::new(90, true),
+            Some(8) => Orientation::new(270, false),
+            _ => Orientation::default(),
+        }
+    }
+
+    /// Returns the orientation applying `self` first and `next` after it.
+    pub fn then(self, next: Orientation) -> Self {
+        let rotate = match next.mirror {
+            true => 360 - self.rotate,
+            false => self.rotate,
+        };
+        Orientation::new(rotate + next.rotate, self.mirror ^ next.mirror)
+    }
+
+    pub fn is_transposed(&self) -> bool {
+        self.rotate == 90 || self.rotate == 270
+    }
+
+    pub fn size(&self, size: (u32, u32)) -> (u32, u32) {
+        match self.is_transposed() {
+            true => (size.1, size.0),
+            false => size,
+        }
+    }
+
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        let mirrored;
+        let image = match self.mirror {
+            true => {
+                mirrored = imageops::flip_horizontal(image);
+                &mirrored
+            }
+            false => image,
+        };
+        match self.rotate {
+            90 => imageops::rotate90(image),
+            180 => imageops::rotate180(image),
+            270 => imageops::rotate270(image),
+            _ => image.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: Rgba<u8> = Rgba([0xff, 0, 0, 0xff]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 0xff, 0xff]);
+
+    // 3x2 image, with a different pixel in every position
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 0xff]))
+    }
+
+    fn all() -> Vec<Orientation> {
+        [false, true]
+            .iter()
+            .flat_map(|&mirror| [0, 90, 180, 270].map(|rotate| Orientation::new(rotate, mirror)))
+            .collect()
+    }
+
+    /// A TIFF with nothing but the orientation tag.
+    fn exif(value: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        tiff.extend(value.to_le_bytes());
+        tiff.extend([0, 0, 0, 0, 0, 0]);
+        tiff
+    }
+
+    #[test]
+    fn applies_rotation_and_mirror() {
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { RED } else { BLUE });
+
+        let rotated = Orientation::new(90, false).apply(&image);
+        assert_eq!(rotated.dimensions(), (1, 2));
+        assert_eq!(*rotated.get_pixel(0, 0), RED);
+        assert_eq!(*rotated.get_pixel(0, 1), BLUE);
+
+        let mirrored = Orientation::new(0, true).apply(&image);
+        assert_eq!(*mirrored.get_pixel(0, 0), BLUE);
+
+        // Mirrored first, then rotated
+        let both = Orientation::new(90, true).apply(&image);
+        assert_eq!(*both.get_pixel(0, 0), BLUE);
+        assert_eq!(*both.get_pixel(0, 1), RED);
+
+        assert_eq!(Orientation::default().apply(&image), image);
+        assert_eq!(Orientation::new(450, false), Orientation::new(90, false));
+    }
+
+    #[test]
+    fn sizes_transposed_orientations() {
+        for orientation in all() {
+            assert_eq!(
+                orientation.size((3, 2)),
+                orientation.apply(&image()).dimensions()
+            );
+        }
+    }
+
+    #[test]
+    fn combines_orientations() {
+        for first in all() {
+            for next in all() {
+                assert_eq!(
+                    first.then(next).apply(&image()),
+                    next.apply(&first.apply(&image())),
+                    "{:?} then {:?}",
+                    first,
+                    next
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn parses_rotate_and_flip() {
+        assert_eq!(
+            Orientation::from_str(None, None),
+            Some(Orientation::default())
+        );
+        assert_eq!(
+            Orientation::from_str(Some(270), Some("")),
+            Some(Orientation::new(270, false))
+        );
+        assert_eq!(
+            Orientation::from_str(Some(90), Some("h")),
+            Some(Orientation::new(90, true))
+        );
+        assert_eq!(
+            Orientation::from_str(None, Some("vertical")),
+            Some(Orientation::new(180, true))
+        );
+        assert_eq!(Orientation::from_str(Some(45), None), None);
+        assert_eq!(Orientation::from_str(Some(360), None), None);
+        assert_eq!(Orientation::from_str(None, Some("x")), None);
+    }
+
+    #[test]
+    fn reads_exif_orientation() {
+        let expected = [
+            (1, Orientation::new(0, false)),
+            (2, Orientation::new(0, true)),
+            (3, Orientation::new(180, false)),
+            (4, Orientation::new(180, true)),
+            (5, Orientation::new(270, true)),
+            (6, Orientation::new(90, false)),
+            (7, Orientation::new(90, true)),
+            (8, Orientation::new(270, false)),
+            (9, Orientation::default()),
+        ];
+        for (value, orientation) in expected {
+            assert_eq!(
+                Orientation::from_exif(&exif(value)),
+                orientation,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            Orientation::from_exif(b"not an image"),
+            Orientation::default()
+        );
+    }
+
+    #[test]
+    fn exif_orientation_turns_image_upright() {
+        // The upright image, as a camera would store it for each EXIF orientation
+        let upright = image();
+        let stored = [
+            (2, imageops::flip_horizontal(&upright)),
+            (3, imageops::rotate180(&upright)),
+            (4, imageops::flip_vertical(&upright)),
+            (5, imageops::rotate90(&imageops::flip_vertical(&upright))),
+            (6, imageops::rotate270(&upright)),
+            (7, imageops::rotate270(&imageops::flip_vertical(&upright))),
+            (8, imageops::rotate90(&upright)),
+        ];
+        for (value, stored) in stored {
+            let orientation = Orientation::from_exif(&exif(value));
+            assert_eq!(orientation.apply(&stored), upright, "{}", value);
+        }
+    }
+}
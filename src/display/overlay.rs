@@ -0,0 +1,357 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::collections::BTreeMap;
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use chrono::format::{Item, StrftimeItems};
+use gotham::handler::IntoResponse;
+use gotham::hyper::{Body, Response, StatusCode};
+use gotham::state::State;
+use image::{Pixel, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::display::result::*;
+
+const FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+pub const DEFAULT_CLOCK_FORMAT: &str = "%H:%M";
+/// Characters of overlay text or clock format, enough for a few lines of a note.
+pub const MAX_TEXT_LENGTH: usize = 1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub fn from_str(anchor: &str) -> Self {
+        match anchor {
+            "top_left" => Anchor::TopLeft,
+            "top" => Anchor::Top,
+            "top_right" => Anchor::TopRight,
+            "left" => Anchor::Left,
+            "center" => Anchor::Center,
+            "right" => Anchor::Right,
+            "bottom_left" => Anchor::BottomLeft,
+            "bottom" => Anchor::Bottom,
+            _ => Anchor::BottomRight,
+        }
+    }
+
+    /// Share of the free space placed before the box, horizontally and vertically.
+    fn align(&self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0f32, 0f32),
+            Anchor::Top => (0.5, 0f32),
+            Anchor::TopRight => (1f32, 0f32),
+            Anchor::Left => (0f32, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1f32, 0.5),
+            Anchor::BottomLeft => (0f32, 1f32),
+            Anchor::Bottom => (0.5, 1f32),
+            Anchor::BottomRight => (1f32, 1f32),
+        }
+    }
+}
+
+/// Title and date taken of the displayed image.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Caption {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+}
+
+impl Caption {
+    pub fn from_exif(body: &[u8]) -> Self {
+        let mut cur = std::io::Cursor::new(body);
+        let exif = match exif::Reader::new().read_from_container(&mut cur) {
+            Ok(exif) => exif,
+            Err(_) => return Caption::default(),
+        };
+        let text = |tag| match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+            Some(exif::Value::Ascii(values)) => values
+                .first()
+                .map(|value| String::from_utf8_lossy(value).trim().to_string())
+                .filter(|value| !value.is_empty()),
+            _ => None,
+        };
+        Caption {
+            title: text(exif::Tag::ImageDescription),
+            // "YYYY:MM:DD HH:MM:SS"
+            date: text(exif::Tag::DateTimeOriginal)
+                .and_then(|date| date.get(..10).map(|date| date.replace(':', "-"))),
+        }
+    }
+
+    fn text(&self) -> String {
+        let lines: Vec<&str> = self
+            .title
+            .iter()
+            .chain(&self.date)
+            .map(String::as_str)
+            .collect();
+        lines.join("\n")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OverlayContent {
+    Text { text: String },
+    Clock { format: String },
+    Caption,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Overlay {
+    #[serde(flatten)]
+    pub content: OverlayContent,
+    pub anchor: Anchor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+    #[serde(serialize_with = "color_serde")]
+    pub color: Rgba<u8>,
+    #[serde(serialize_with = "color_serde")]
+    pub background: Rgba<u8>,
+}
+
+fn color_serde<S>(color: &Rgba<u8>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let [r, g, b, a] = color.0;
+    s.serialize_str(&format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a))
+}
+
+/// Parses `RRGGBB` or `RRGGBBAA`, with or without a leading `#`.
+pub fn parse_color(color: &str) -> Option<Rgba<u8>> {
+    let hex = color.trim_start_matches('#');
+    // from_str_radix alone would accept a leading '+'
+    if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => {
+            let [_, r, g, b] = value.to_be_bytes();
+            Some(Rgba([r, g, b, 0xff]))
+        }
+        8 => Some(Rgba(value.to_be_bytes())),
+        _ => None,
+    }
+}
+
+pub fn is_valid_clock_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+impl Overlay {
+    pub fn is_clock(&self) -> bool {
+        matches!(self.content, OverlayContent::Clock { .. })
+    }
+
+    fn text(&self, caption: &Caption) -> String {
+        match &self.content {
+            OverlayContent::Text { text } => text.clone(),
+            OverlayContent::Clock { format } => chrono::Local::now().format(format).to_string(),
+            OverlayContent::Caption => caption.text(),
+        }
+    }
+
+    fn draw(&self, frame: &mut RgbaImage, caption: &Caption) {
+        let text = self.text(caption);
+        if text.trim().is_empty() {
+            return;
+        }
+
+        let font = FontRef::try_from_slice(FONT).expect("embedded font");
+        let (vw, vh) = frame.dimensions();
+        let size = self.size.unwrap_or(vh / 20).min(vh).max(8) as f32;
+        let font = font.as_scaled(PxScale::from(size));
+
+        let lines: Vec<&str> = text.lines().collect();
+        let line_height = font.height() + font.line_gap();
+        let width = lines
+            .iter()
+            .map(|line| line_width(&font, line))
+            .fold(0f32, f32::max);
+        let height = line_height * lines.len() as f32 - font.line_gap();
+
+        let (padding, margin) = (size * 0.3, size * 0.5);
+        let (bw, bh) = (width + padding * 2f32, height + padding * 2f32);
+        let (ax, ay) = self.anchor.align();
+        let x = margin + (vw as f32 - bw - margin * 2f32) * ax;
+        let y = margin + (vh as f32 - bh - margin * 2f32) * ay;
+
+        fill_rect(frame, (x, y, bw, bh), self.background);
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = y + padding + font.ascent() + line_height * index as f32;
+            // Only rasterize the lines that are on screen
+            if baseline - font.ascent() >= vh as f32 {
+                break;
+            }
+            if baseline - font.descent() < 0f32 {
+                continue;
+            }
+            draw_line(frame, &font, line, (x + padding, baseline), self.color);
+        }
+    }
+}
+
+fn line_width<F: Font, SF: ScaleFont<F>>(font: &SF, line: &str) -> f32 {
+    let mut last = None;
+    line.chars()
+        .map(|c| {
+            let id = font.glyph_id(c);
+            let kern = last.map_or(0f32, |last| font.kern(last, id));
+            last = Some(id);
+            kern + font.h_advance(id)
+        })
+        .sum()
+}
+
+fn blend(frame: &mut RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    let (vw, vh) = frame.dimensions();
+    if x < 0 || y < 0 || x >= vw as i32 || y >= vh as i32 {
+        return;
+    }
+    frame.get_pixel_mut(x as u32, y as u32).blend(&color);
+}
+
+fn fill_rect(frame: &mut RgbaImage, rect: (f32, f32, f32, f32), color: Rgba<u8>) {
+    if color[3] == 0 {
+        return;
+    }
+    let (vw, vh) = frame.dimensions();
+    let (x, y, w, h) = rect;
+    let clamp = |value: f32, max: u32| value.max(0f32).min(max as f32) as u32;
+    for py in clamp(y, vh)..clamp(y + h, vh) {
+        for px in clamp(x, vw)..clamp(x + w, vw) {
+            frame.get_pixel_mut(px, py).blend(&color);
+        }
+    }
+}
+
+fn draw_line<F: Font, SF: ScaleFont<F>>(
+    frame: &mut RgbaImage,
+    font: &SF,
+    line: &str,
+    origin: (f32, f32),
+    color: Rgba<u8>,
+) {
+    let (vw, vh) = frame.dimensions();
+    let mut caret = point(origin.0, origin.1);
+    let mut last = None;
+    for c in line.chars() {
+        // The rest of the line is past the right edge
+        if caret.x >= vw as f32 {
+            break;
+        }
+        let id = font.glyph_id(c);
+        if let Some(last) = last {
+            caret.x += font.kern(last, id);
+        }
+        let glyph = id.with_scale_and_position(font.scale(), caret);
+        caret.x += font.h_advance(id);
+        last = Some(id);
+
+        let outlined = match font.outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            None => continue,
+        };
+        let bounds = outlined.px_bounds();
+        if bounds.max.x < 0f32
+            || bounds.max.y < 0f32
+            || bounds.min.x >= vw as f32
+            || bounds.min.y >= vh as f32
+        {
+            continue;
+        }
+        outlined.draw(|gx, gy, coverage| {
+            let mut color = color;
+            color[3] = (color[3] as f32 * coverage.min(1f32)) as u8;
+            blend(
+                frame,
+                bounds.min.x as i32 + gx as i32,
+                bounds.min.y as i32 + gy as i32,
+                color,
+            );
+        });
+    }
+}
+
+/// Draws the overlays onto `frame` in order of their names.
+pub fn draw_overlays<'a>(
+    frame: &mut RgbaImage,
+    overlays: impl Iterator<Item = &'a Overlay>,
+    caption: &Caption,
+) {
+    for overlay in overlays {
+        overlay.draw(frame, caption);
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct OverlayResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overlay: Option<Overlay>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overlays: Option<BTreeMap<String, Overlay>>,
+}
+
+impl IntoResponse for OverlayResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_huge_text_quickly() {
+        let overlay = Overlay {
+            content: OverlayContent::Text {
+                text: format!("{}\n", "W".repeat(200)).repeat(50),
+            },
+            anchor: Anchor::Center,
+            size: Some(480),
+            color: Rgba([0xff; 4]),
+            background: Rgba([0, 0, 0, 0x80]),
+        };
+        let mut frame = RgbaImage::new(640, 480);
+        let start = std::time::Instant::now();
+        draw_overlays(&mut frame, std::iter::once(&overlay), &Caption::default());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        // The box covers the whole frame
+        assert_ne!(frame.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_ne!(frame.get_pixel(639, 479), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn parses_hex_color() {
+        assert_eq!(parse_color("#20a0ff"), Some(Rgba([0x20, 0xa0, 0xff, 0xff])));
+        assert_eq!(
+            parse_color("20a0ff80"),
+            Some(Rgba([0x20, 0xa0, 0xff, 0x80]))
+        );
+        assert_eq!(parse_color("#+20a0f"), None);
+        assert_eq!(parse_color("+20a0ff8"), None);
+        assert_eq!(parse_color("#20a0f"), None);
+    }
+}
@@ -0,0 +1,29 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use gotham::handler::IntoResponse;
+use gotham::hyper::{Body, Response, StatusCode};
+use gotham::state::State;
+use serde::Serialize;
+
+use crate::display::result::*;
+use crate::slideshow::*;
+
+#[derive(Debug, Serialize, Default)]
+pub struct PlaylistResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slide: Option<Slide>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playlist: Option<Playlist>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<crate::error::ImageError>,
+}
+
+impl IntoResponse for PlaylistResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
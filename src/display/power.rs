@@ -3,10 +3,11 @@ Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
 use gotham::handler::IntoResponse;
-use gotham::helpers::http::response::create_response;
 use gotham::hyper::{Body, Response, StatusCode};
 use gotham::state::State;
-use serde::Serialize;
+use serde::{de::Error, Deserialize, Serialize};
+
+use crate::display::result::*;
 
 #[derive(Debug, Serialize, Default)]
 pub struct DisplayPower {
@@ -16,20 +17,277 @@ pub struct DisplayPower {
     pub power: Option<bool>,
 }
 
-fn status_serde<S>(status: &StatusCode, s: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    s.serialize_str(&format!("{}", status.as_u16()))
+/// Output state as reported by the display hardware.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DisplayInfo {
+    pub attached: bool,
+    pub power: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_rate: Option<u32>,
+    pub interlaced: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct DisplayInfoResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<DisplayInfo>,
+}
+
+impl IntoResponse for DisplayInfoResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Day {
+    pub fn from_index(index: u32) -> Self {
+        match index % 7 {
+            0 => Day::Mon,
+            1 => Day::Tue,
+            2 => Day::Wed,
+            3 => Day::Thu,
+            4 => Day::Fri,
+            5 => Day::Sat,
+            _ => Day::Sun,
+        }
+    }
+
+    fn previous(&self) -> Self {
+        Day::from_index(*self as u32 + 6)
+    }
+}
+
+/// Minutes since midnight, written as `HH:MM`.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    pub fn new(hour: u32, minute: u32) -> Option<Self> {
+        match hour < 24 && minute < 60 {
+            true => Some(TimeOfDay(hour * 60 + minute)),
+            false => None,
+        }
+    }
+
+    pub fn from_str(time: &str) -> Option<Self> {
+        let (hour, minute) = time.split_once(':')?;
+        TimeOfDay::new(hour.parse().ok()?, minute.parse().ok()?)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.serialize_str(&format!("{:02}:{:02}", self.0 / 60, self.0 % 60))
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let time = String::deserialize(d)?;
+        TimeOfDay::from_str(&time).ok_or_else(|| D::Error::custom("expected HH:MM"))
+    }
+}
+
+/// Keeps the display on from `on` until `off`; windows ending before they start run past midnight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PowerWindow {
+    /// Days the window starts on; every day when empty.
+    #[serde(default)]
+    pub days: Vec<Day>,
+    pub on: TimeOfDay,
+    pub off: TimeOfDay,
+}
+
+impl PowerWindow {
+    fn starts_on(&self, day: Day) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    pub fn contains(&self, day: Day, time: TimeOfDay) -> bool {
+        if self.on <= self.off {
+            self.starts_on(day) && self.on <= time && time < self.off
+        } else {
+            (self.starts_on(day) && self.on <= time)
+                || (self.starts_on(day.previous()) && time < self.off)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerSchedule {
+    pub windows: Vec<PowerWindow>,
+}
+
+impl PowerSchedule {
+    /// Whether the display should be on, or `None` without any windows.
+    pub fn power(&self, day: Day, time: TimeOfDay) -> Option<bool> {
+        match self.windows.is_empty() {
+            true => None,
+            false => Some(self.windows.iter().any(|window| window.contains(day, time))),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.windows.iter().all(|window| window.on != window.off)
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct ScheduleResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<PowerSchedule>,
+}
+
+impl IntoResponse for ScheduleResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
 }
 
 impl IntoResponse for DisplayPower {
     fn into_response(self, state: &State) -> Response<Body> {
-        create_response(
-            state,
-            self.status,
-            mime::APPLICATION_JSON,
-            serde_json::to_string(&self).expect("serialize JSON"),
-        )
+        json_response(state, self.status, &self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> TimeOfDay {
+        TimeOfDay::from_str(time).unwrap()
+    }
+
+    fn window(days: Vec<Day>, on: &str, off: &str) -> PowerWindow {
+        PowerWindow {
+            days,
+            on: time(on),
+            off: time(off),
+        }
+    }
+
+    #[test]
+    fn parses_time_of_day() {
+        assert_eq!(TimeOfDay::from_str("00:00"), TimeOfDay::new(0, 0));
+        assert_eq!(TimeOfDay::from_str("7:05"), TimeOfDay::new(7, 5));
+        assert_eq!(TimeOfDay::from_str("23:59"), TimeOfDay::new(23, 59));
+        assert_eq!(TimeOfDay::from_str("24:00"), None);
+        assert_eq!(TimeOfDay::from_str("12:60"), None);
+        assert_eq!(TimeOfDay::from_str("12"), None);
+        assert_eq!(TimeOfDay::from_str("ab:cd"), None);
+        assert_eq!(TimeOfDay::from_str("-1:00"), None);
+    }
+
+    #[test]
+    fn serializes_time_of_day() {
+        assert_eq!(serde_json::to_string(&time("7:05")).unwrap(), "\"07:05\"");
+        let parsed: TimeOfDay = serde_json::from_str("\"23:30\"").unwrap();
+        assert_eq!(parsed, time("23:30"));
+        assert!(serde_json::from_str::<TimeOfDay>("\"24:00\"").is_err());
+        assert!(serde_json::from_str::<TimeOfDay>("\"12:60\"").is_err());
+
+        let window: PowerWindow =
+            serde_json::from_str(r#"{"days": ["sat", "sun"], "on": "09:00", "off": "17:00"}"#)
+                .unwrap();
+        assert_eq!(window.days, vec![Day::Sat, Day::Sun]);
+        assert_eq!(
+            serde_json::to_value(&window).unwrap(),
+            serde_json::json!({"days": ["sat", "sun"], "on": "09:00", "off": "17:00"})
+        );
+    }
+
+    #[test]
+    fn contains_window_boundaries() {
+        let window = window(vec![], "08:00", "18:00");
+        assert!(!window.contains(Day::Mon, time("07:59")));
+        assert!(window.contains(Day::Mon, time("08:00")));
+        assert!(window.contains(Day::Sun, time("17:59")));
+        assert!(!window.contains(Day::Sun, time("18:00")));
+    }
+
+    #[test]
+    fn contains_window_on_listed_days() {
+        let window = window(vec![Day::Mon, Day::Wed], "08:00", "18:00");
+        assert!(window.contains(Day::Mon, time("12:00")));
+        assert!(!window.contains(Day::Tue, time("12:00")));
+        assert!(window.contains(Day::Wed, time("12:00")));
+    }
+
+    #[test]
+    fn contains_window_past_midnight() {
+        let window = window(vec![Day::Fri], "22:00", "02:00");
+        assert!(!window.contains(Day::Fri, time("21:59")));
+        assert!(window.contains(Day::Fri, time("22:00")));
+        assert!(window.contains(Day::Fri, time("23:59")));
+        assert!(window.contains(Day::Sat, time("00:00")));
+        assert!(window.contains(Day::Sat, time("01:00")));
+        assert!(!window.contains(Day::Sat, time("02:00")));
+        assert!(!window.contains(Day::Sat, time("22:00")));
+        assert!(!window.contains(Day::Fri, time("01:00")));
+    }
+
+    #[test]
+    fn contains_window_past_midnight_on_sunday() {
+        let window = window(vec![Day::Sun], "23:00", "01:00");
+        assert!(window.contains(Day::Mon, time("00:30")));
+        assert!(!window.contains(Day::Sun, time("00:30")));
+    }
+
+    #[test]
+    fn decides_power() {
+        assert_eq!(
+            PowerSchedule::default().power(Day::Mon, time("12:00")),
+            None
+        );
+
+        let schedule = PowerSchedule {
+            windows: vec![
+                window(vec![], "07:00", "09:00"),
+                window(vec![Day::Fri], "22:00", "02:00"),
+            ],
+        };
+        assert_eq!(schedule.power(Day::Mon, time("08:00")), Some(true));
+        assert_eq!(schedule.power(Day::Mon, time("12:00")), Some(false));
+        assert_eq!(schedule.power(Day::Sat, time("01:00")), Some(true));
+        assert_eq!(schedule.power(Day::Sat, time("03:00")), Some(false));
+    }
+
+    #[test]
+    fn validates_schedule() {
+        assert!(PowerSchedule::default().is_valid());
+        let mut schedule = PowerSchedule {
+            windows: vec![window(vec![], "22:00", "02:00")],
+        };
+        assert!(schedule.is_valid());
+        schedule.windows.push(window(vec![], "08:00", "08:00"));
+        assert!(!schedule.is_valid());
     }
 }
@@ -3,9 +3,90 @@ Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
 
-use crate::display::image::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+use crate::display::{image::*, orientation::*};
+
+/// Point of the image, in 0..1 of its displayed size, kept in view when it overflows.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Focus {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Default for Focus {
+    fn default() -> Self {
+        Focus { x: 0.5, y: 0.5 }
+    }
+}
+
+impl Focus {
+    pub fn new(x: Option<f32>, y: Option<f32>) -> Self {
+        let clamp = |value: Option<f32>| value.unwrap_or(0.5).clamp(0f32, 1f32);
+        Focus {
+            x: clamp(x),
+            y: clamp(y),
+        }
+    }
+}
+
+/// Region of the image, in 0..1 of its displayed size.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl CropRect {
+    /// Parses `x,y,w,h`.
+    pub fn from_str(crop: &str) -> Option<Self> {
+        let values: Vec<f32> = crop
+            .split(',')
+            .map(|value| value.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match values[..] {
+            [x, y, w, h]
+                if x >= 0f32
+                    && y >= 0f32
+                    && w > 0f32
+                    && h > 0f32
+                    && x + w <= 1f32
+                    && y + h <= 1f32 =>
+            {
+                Some(CropRect { x, y, w, h })
+            }
+            _ => None,
+        }
+    }
+
+    /// Maps the region of the displayed image back onto the stored pixels.
+    pub fn unorient(&self, orientation: Orientation) -> Self {
+        let point = |u: f32, v: f32| {
+            let (u, v) = match orientation.rotate {
+                90 => (v, 1f32 - u),
+                180 => (1f32 - u, 1f32 - v),
+                270 => (1f32 - v, u),
+                _ => (u, v),
+            };
+            match orientation.mirror {
+                true => (1f32 - u, v),
+                false => (u, v),
+            }
+        };
+        let (x0, y0) = point(self.x, self.y);
+        let (x1, y1) = point(self.x + self.w, self.y + self.h);
+        CropRect {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            w: (x1 - x0).abs(),
+            h: (y1 - y0).abs(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub struct DisplayRect {
     pub x: i16,
     pub y: i16,
@@ -14,19 +95,25 @@ pub struct DisplayRect {
 }
 
 impl DisplayRect {
-    pub fn new_with_mode(mode: ContentMode, viewport: (u32, u32), image: (u32, u32)) -> Self {
-        let ((vw, vh), (w, h)) = (viewport, image);
+    pub fn new_with_mode(
+        mode: ContentMode,
+        viewport: (u32, u32),
+        image: (u32, u32),
+        orientation: Orientation,
+        focus: Focus,
+    ) -> Self {
+        let ((vw, vh), (w, h)) = (viewport, orientation.size(image));
         let viewport_aspect = vw as f32 / vh as f32;
         let image_aspect = w as f32 / h as f32;
         let ratio = image_aspect / viewport_aspect;
         let (vw, vh, w, h) = (vw as i16, vh as i16, w as i16, h as i16);
 
-        match mode {
+        let rect = match mode {
             ContentMode::None => Self {
                 x: (vw - w) / 2,
                 y: (vh - h) / 2,
-                w: w,
-                h: h,
+                w,
+                h,
             },
             ContentMode::ScaleToFill => Self {
                 x: 0i16,
@@ -52,7 +139,7 @@ impl DisplayRect {
                     Self {
                         x: ((vw - w) / 2),
                         y: 0,
-                        w: w,
+                        w,
                         h: vh,
                     }
                 } else {
@@ -60,10 +147,180 @@ impl DisplayRect {
                         x: 0,
                         y: ((vh - h) / 2),
                         w: vw,
-                        h: h,
+                        h,
                     }
                 }
             }
+        };
+        rect.with_focus(focus, (vw, vh))
+    }
+
+    /// Shifts a rect overflowing the viewport to bring `focus` as close to the center as possible.
+    fn with_focus(self, focus: Focus, viewport: (i16, i16)) -> Self {
+        let align = |offset: i16, size: i16, viewport: i16, focus: f32| match size > viewport {
+            true => ((viewport as f32 / 2f32 - focus * size as f32) as i16)
+                .max(viewport - size)
+                .min(0),
+            false => offset,
+        };
+        Self {
+            x: align(self.x, self.w, viewport.0, focus.x),
+            y: align(self.y, self.h, viewport.1, focus.y),
+            ..self
+        }
+    }
+
+    /// Maps a rect in the viewport rotated clockwise by `rotation` back onto the panel.
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
+    pub fn rotate(&self, rotation: u32, viewport: (u32, u32)) -> Self {
+        let (vw, vh) = (viewport.0 as i16, viewport.1 as i16);
+        let DisplayRect { x, y, w, h } = *self;
+        match rotation {
+            90 => Self {
+                x: vh - y - h,
+                y: x,
+                w: h,
+                h: w,
+            },
+            180 => Self {
+                x: vw - x - w,
+                y: vh - y - h,
+                w,
+                h,
+            },
+            270 => Self {
+                x: y,
+                y: vw - x - w,
+                w: h,
+                h: w,
+            },
+            _ => Self { x, y, w, h },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, ImageFormat, Rgba, RgbaImage};
+
+    fn offset(rect: DisplayRect) -> (i16, i16, i16, i16) {
+        (rect.x, rect.y, rect.w, rect.h)
+    }
+
+    fn fill(image: (u32, u32), focus: Focus) -> (i16, i16, i16, i16) {
+        offset(DisplayRect::new_with_mode(
+            ContentMode::Aspect(AspectMode::Fill),
+            (100, 100),
+            image,
+            Orientation::default(),
+            focus,
+        ))
+    }
+
+    #[test]
+    fn focus_keeps_point_in_view() {
+        let focus = |x, y| Focus::new(Some(x), Some(y));
+        assert_eq!(fill((200, 100), Focus::default()), (-50, 0, 200, 100));
+        assert_eq!(fill((200, 100), focus(0.0, 0.5)), (0, 0, 200, 100));
+        assert_eq!(fill((200, 100), focus(1.0, 0.5)), (-100, 0, 200, 100));
+        assert_eq!(fill((200, 100), focus(0.9, 0.5)), (-100, 0, 200, 100));
+        assert_eq!(fill((100, 400), focus(0.5, 0.0)), (0, 0, 100, 400));
+        assert_eq!(fill((100, 400), focus(0.5, 1.0)), (0, -300, 100, 400));
+        assert_eq!(fill((100, 400), focus(0.5, 0.25)), (0, -50, 100, 400));
+    }
+
+    #[test]
+    fn focus_leaves_fitting_rect_alone() {
+        let rect = DisplayRect {
+            x: 10,
+            y: 20,
+            w: 80,
+            h: 60,
+        };
+        let focus = Focus::new(Some(0.0), Some(1.0));
+        assert_eq!(offset(rect.with_focus(focus, (100, 100))), (10, 20, 80, 60));
+        // Out of range values are clamped to the edges
+        assert_eq!(Focus::new(Some(-1.0), Some(2.0)), Focus { x: 0.0, y: 1.0 });
+    }
+
+    #[test]
+    fn parses_crop() {
+        assert_eq!(
+            CropRect::from_str("0.25, 0,0.5 ,1"),
+            Some(CropRect {
+                x: 0.25,
+                y: 0.0,
+                w: 0.5,
+                h: 1.0
+            })
+        );
+        for crop in [
+            "",
+            "0,0,1",
+            "0,0,1,1,1",
+            "a,b,c,d",
+            "0;0;1;1",
+            "nan,0,1,1",
+            "0,0,inf,1",
+            "0,0,0,1",
+            "-0.1,0,0.5,0.5",
+            "0.5,0,0.6,1",
+            "0,0.75,1,0.5",
+        ] {
+            assert_eq!(CropRect::from_str(crop), None, "{}", crop);
+        }
+    }
+
+    #[test]
+    fn unorients_crop() {
+        let crop = CropRect {
+            x: 0.0,
+            y: 0.0,
+            w: 0.25,
+            h: 0.5,
+        };
+        let unorient = |rotate, mirror| {
+            let CropRect { x, y, w, h } = crop.unorient(Orientation::new(rotate, mirror));
+            (x, y, w, h)
+        };
+        assert_eq!(unorient(0, false), (0.0, 0.0, 0.25, 0.5));
+        assert_eq!(unorient(90, false), (0.0, 0.75, 0.5, 0.25));
+        assert_eq!(unorient(180, false), (0.75, 0.5, 0.25, 0.5));
+        assert_eq!(unorient(270, false), (0.5, 0.0, 0.5, 0.25));
+        assert_eq!(unorient(0, true), (0.75, 0.0, 0.25, 0.5));
+    }
+
+    #[test]
+    fn crops_in_every_orientation() {
+        // 8x4 image, with a different pixel in every position
+        let source = RgbaImage::from_fn(8, 4, |x, y| Rgba([x as u8, y as u8, 0, 0xff]));
+        let crop = CropRect::from_str("0.25,0,0.5,0.75").unwrap();
+        for mirror in [false, true] {
+            for rotate in [0, 90, 180, 270] {
+                let orientation = Orientation::new(rotate, mirror);
+                let mut image = DisplayImage::new(source.clone(), 0, ImageFormat::Png);
+                image.set_orientation(orientation);
+                image.set_crop(crop);
+
+                let displayed = orientation.apply(&source);
+                let (w, h) = displayed.dimensions();
+                let expected = imageops::crop_imm(
+                    &displayed,
+                    (crop.x * w as f32) as u32,
+                    (crop.y * h as f32) as u32,
+                    (crop.w * w as f32) as u32,
+                    (crop.h * h as f32) as u32,
+                )
+                .to_image();
+                assert_eq!(
+                    orientation.apply(&image.to_rgba_image()),
+                    expected,
+                    "{:?}",
+                    orientation
+                );
+                assert_eq!(image.crop(), Some(crop));
+            }
         }
     }
 }
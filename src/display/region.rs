@@ -0,0 +1,117 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use gotham::handler::IntoResponse;
+use gotham::hyper::{Body, Response, StatusCode};
+use gotham::state::State;
+use serde::Serialize;
+
+use crate::display::{image::*, rect::*, result::*};
+
+/// Area of the viewport showing its own image above the main one.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Region {
+    pub x: i16,
+    pub y: i16,
+    pub w: i16,
+    pub h: i16,
+    pub layer: i32,
+    pub opacity: f32,
+}
+
+impl Region {
+    /// Clamps the rect to the viewport, or returns `None` if nothing of it is visible.
+    pub fn new(
+        rect: DisplayRect,
+        viewport: (u32, u32),
+        layer: Option<i32>,
+        opacity: Option<f32>,
+    ) -> Option<Self> {
+        let DisplayRect { x, y, w, h } = rect;
+        let layer = layer.unwrap_or(1);
+        if w <= 0 || h <= 0 || layer < 1 {
+            return None;
+        }
+        // i32, as x + w may not fit in i16
+        let (x, y, w, h) = (x as i32, y as i32, w as i32, h as i32);
+        let (vw, vh) = (viewport.0 as i32, viewport.1 as i32);
+        let (left, top) = (x.max(0), y.max(0));
+        let (right, bottom) = ((x + w).min(vw), (y + h).min(vh));
+        if right <= left || bottom <= top {
+            return None;
+        }
+        Some(Region {
+            x: left as i16,
+            y: top as i16,
+            w: (right - left) as i16,
+            h: (bottom - top) as i16,
+            layer,
+            opacity: opacity.unwrap_or(1f32).clamp(0f32, 1f32),
+        })
+    }
+
+    // The dummy pipeline does not draw regions
+    #[cfg(any(
+        all(target_os = "linux", feature = "raspberry-pi"),
+        feature = "software",
+        feature = "fbdev"
+    ))]
+    pub fn size(&self) -> (u32, u32) {
+        (self.w as u32, self.h as u32)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub region: Region,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_mode: Option<ContentMode>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct RegionResult {
+    #[serde(serialize_with = "status_serde")]
+    pub status: StatusCode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<RegionInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regions: Option<Vec<RegionInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<crate::error::ImageError>,
+}
+
+impl IntoResponse for RegionResult {
+    fn into_response(self, state: &State) -> Response<Body> {
+        json_response(state, self.status, &self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i16, y: i16, w: i16, h: i16) -> Option<(i16, i16, i16, i16)> {
+        Region::new(DisplayRect { x, y, w, h }, (640, 480), None, None)
+            .map(|region| (region.x, region.y, region.w, region.h))
+    }
+
+    #[test]
+    fn clamps_to_viewport() {
+        assert_eq!(region(10, 20, 100, 50), Some((10, 20, 100, 50)));
+        assert_eq!(region(-10, 400, 100, 200), Some((0, 400, 90, 80)));
+        assert_eq!(region(600, 0, i16::MAX, i16::MAX), Some((600, 0, 40, 480)));
+    }
+
+    #[test]
+    fn rejects_empty_region() {
+        assert_eq!(region(0, 0, 0, 50), None);
+        assert_eq!(region(0, 0, 100, -1), None);
+        assert_eq!(region(640, 0, 100, 50), None);
+        assert_eq!(region(-100, 0, 100, 50), None);
+    }
+}
@@ -8,34 +8,50 @@ use gotham::hyper::{Body, Response, StatusCode};
 use gotham::state::State;
 use serde::Serialize;
 
-use crate::display::image::*;
+use crate::display::{image::*, transition::*};
 
 #[derive(Debug, Serialize, Default)]
 pub struct DisplayResult {
     #[serde(serialize_with = "status_serde")]
     pub status: StatusCode,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub image: Option<DisplayImage>,
+    pub image: Option<ImageInfo>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content_mode: Option<ContentMode>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shown_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<crate::error::ImageError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_error: Option<crate::error::FetchError>,
 }
 
-fn status_serde<S>(status: &StatusCode, s: S) -> Result<S::Ok, S::Error>
+/// Serializes a status code as a string, e.g. "200".
+pub fn status_serde<S>(status: &StatusCode, s: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
     s.serialize_str(&format!("{}", status.as_u16()))
 }
 
+/// Responds with the result serialized as JSON.
+pub fn json_response<T: Serialize>(
+    state: &State,
+    status: StatusCode,
+    result: &T,
+) -> Response<Body> {
+    create_response(
+        state,
+        status,
+        mime::APPLICATION_JSON,
+        serde_json::to_string(result).expect("serialize JSON"),
+    )
+}
+
 impl IntoResponse for DisplayResult {
     fn into_response(self, state: &State) -> Response<Body> {
-        create_response(
-            state,
-            self.status,
-            mime::APPLICATION_JSON,
-            serde_json::to_string(&self).expect("serialize JSON"),
-        )
+        json_response(state, self.status, &self)
     }
 }
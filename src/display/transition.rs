@@ -0,0 +1,191 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::time::{Duration, Instant};
+
+use image::{ImageBuffer, Pixel, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::error::{ImageError, PipelineError};
+
+const FRAME_RATE: u64 = 30;
+const DEFAULT_DURATION: u32 = 500;
+const MAX_DURATION: u32 = 10_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransitionKind {
+    None,
+    Crossfade,
+    FadeThroughBlack,
+    Slide,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct Transition {
+    #[serde(serialize_with = "kind_serde")]
+    pub kind: TransitionKind,
+    pub duration: u32,
+}
+
+fn kind_serde<S>(kind: &TransitionKind, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    s.serialize_str(match kind {
+        TransitionKind::None => "none",
+        TransitionKind::Crossfade => "crossfade",
+        TransitionKind::FadeThroughBlack => "fade",
+        TransitionKind::Slide => "slide",
+    })
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            kind: TransitionKind::None,
+            duration: 0,
+        }
+    }
+}
+
+fn mix(from: &Rgba<u8>, to: &Rgba<u8>, progress: f32) -> Rgba<u8> {
+    from.map2(to, |a, b| {
+        (a as f32 + (b as f32 - a as f32) * progress) as u8
+    })
+}
+
+impl Transition {
+    pub fn from_str(kind: &str, duration: Option<u32>) -> Self {
+        let kind = match kind {
+            "Crossfade" | "crossfade" => TransitionKind::Crossfade,
+            "Fade" | "fade" => TransitionKind::FadeThroughBlack,
+            "Slide" | "slide" => TransitionKind::Slide,
+            _ => TransitionKind::None,
+        };
+        let duration = match kind {
+            TransitionKind::None => 0,
+            _ => duration.unwrap_or(DEFAULT_DURATION).min(MAX_DURATION),
+        };
+        Transition { kind, duration }
+    }
+
+    pub fn unsupported_error(&self) -> ImageError {
+        ImageError::invalid_option(format!(
+            "{:?} transition is not supported by this display",
+            self.kind
+        ))
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.kind == TransitionKind::None || self.duration == 0
+    }
+
+    pub fn frames(&self) -> u64 {
+        (self.duration as u64 * FRAME_RATE / 1000).max(1)
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_millis(self.duration as u64 / self.frames())
+    }
+
+    pub fn blend(&self, from: &RgbaImage, to: &RgbaImage, progress: f32) -> RgbaImage {
+        let (width, height) = to.dimensions();
+        let black = Rgba([0, 0, 0, 0xff]);
+        match self.kind {
+            TransitionKind::None => to.clone(),
+            TransitionKind::Crossfade => ImageBuffer::from_fn(width, height, |x, y| {
+                mix(from.get_pixel(x, y), to.get_pixel(x, y), progress)
+            }),
+            TransitionKind::FadeThroughBlack if progress < 0.5 => {
+                ImageBuffer::from_fn(width, height, |x, y| {
+                    mix(from.get_pixel(x, y), &black, progress * 2.0)
+                })
+            }
+            TransitionKind::FadeThroughBlack => ImageBuffer::from_fn(width, height, |x, y| {
+                mix(&black, to.get_pixel(x, y), progress * 2.0 - 1.0)
+            }),
+            TransitionKind::Slide => {
+                let offset = (width as f32 * progress) as u32;
+                ImageBuffer::from_fn(width, height, |x, y| match x + offset {
+                    x if x < width => *from.get_pixel(x, y),
+                    x => *to.get_pixel(x - width, y),
+                })
+            }
+        }
+    }
+
+    /// Calls `frame` with the progress of each frame until the duration has passed.
+    ///
+    /// Frames are scheduled from the start of the transition, and dropped when drawing
+    /// falls behind, so slow frames do not stretch the transition.
+    pub fn each_frame<F>(&self, mut frame: F) -> Result<(), PipelineError>
+    where
+        F: FnMut(f32) -> Result<(), PipelineError>,
+    {
+        let duration = Duration::from_millis(self.duration as u64);
+        let interval = self.frame_interval().max(Duration::from_millis(1));
+        let start = Instant::now();
+        let mut deadline = start + interval;
+        loop {
+            std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            let elapsed = start.elapsed();
+            if elapsed >= duration {
+                return Ok(());
+            }
+            frame(elapsed.as_secs_f32() / duration.as_secs_f32())?;
+            let now = Instant::now();
+            while deadline <= now {
+                deadline += interval;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamps_duration() {
+        let transition = Transition::from_str("fade", Some(200_000_000));
+        assert_eq!(transition.duration, MAX_DURATION);
+        assert_eq!(transition.frames(), 300);
+        assert_eq!(
+            Transition::from_str("fade", None).duration,
+            DEFAULT_DURATION
+        );
+        assert_eq!(Transition::from_str("none", Some(1000)).duration, 0);
+    }
+
+    #[test]
+    fn counts_frames_without_overflow() {
+        let transition = Transition {
+            kind: TransitionKind::Crossfade,
+            duration: u32::MAX,
+        };
+        assert_eq!(transition.frames(), u32::MAX as u64 * 30 / 1000);
+        assert_eq!(transition.frame_interval(), Duration::from_millis(33));
+    }
+
+    #[test]
+    fn drops_frames_instead_of_stretching() {
+        let transition = Transition::from_str("crossfade", Some(300));
+        let start = Instant::now();
+        let mut progress = Vec::new();
+        transition
+            .each_frame(|value| {
+                progress.push(value);
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(())
+            })
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(elapsed < Duration::from_millis(600), "took {:?}", elapsed);
+        assert!(progress.len() < transition.frames() as usize);
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(progress.iter().all(|value| (0f32..1f32).contains(value)));
+    }
+}
@@ -17,39 +17,123 @@ pub mod vc {
         pub fn hdmi_power_on_preferred() {}
         pub fn power_off() {}
     }
+
+    pub mod dispmanx {
+        use crate::error::PipelineError;
+
+        pub fn snapshot(width: u32, height: u32) -> Result<Vec<u8>, PipelineError> {
+            Ok(vec![0u8; (width * height * 4) as usize])
+        }
+    }
 }
 
 pub mod pipeline {
-    use crate::display::image::*;
-    use crate::error::PipelineError;
-    use gotham_derive::*;
+    use crate::backend::DisplayBackend;
+    use crate::display::{frame::*, image::*, power::*, region::*, transition::*};
+    use crate::error::{Operation, PipelineError};
+    use crate::vc::*;
+    use image::RgbaImage;
 
     #[derive(Debug, Default, Copy, Clone)]
-    pub struct Component {}
-
-    #[derive(Debug, Default, Copy, Clone, StateData)]
-    pub struct Pipeline {}
+    pub struct Pipeline {
+        viewport: (u32, u32),
+        rotation: u32,
+        power: bool,
+    }
 
     impl Pipeline {
-        pub fn new(_width: u32, _height: u32) -> Pipeline {
-            Default::default()
+        pub fn new(width: u32, height: u32) -> Pipeline {
+            Pipeline {
+                viewport: (width, height),
+                rotation: 0,
+                power: true,
+            }
+        }
+
+        pub fn set_rotation(&mut self, rotation: u32) {
+            self.rotation = rotation;
         }
+    }
 
-        pub fn init(&mut self) -> Result<(), PipelineError> {
+    impl DisplayBackend for Pipeline {
+        fn init(&mut self) -> Result<(), PipelineError> {
             Ok(())
         }
 
-        pub fn destroy(&mut self) -> Result<(), PipelineError> {
+        fn destroy(&mut self) -> Result<(), PipelineError> {
+            Ok(())
+        }
+
+        fn render_image(
+            &mut self,
+            _image: &DisplayImage,
+            _content_mode: ContentMode,
+            _transition: Transition,
+            _timeout: i32,
+        ) -> Result<(), PipelineError> {
             Ok(())
         }
 
-        pub fn render_image(
+        fn render_region(
             &mut self,
+            _name: &str,
+            _region: Region,
             _image: &DisplayImage,
             _content_mode: ContentMode,
             _timeout: i32,
         ) -> Result<(), PipelineError> {
             Ok(())
         }
+
+        fn remove_region(&mut self, _name: &str) -> Result<(), PipelineError> {
+            Ok(())
+        }
+
+        fn power_on(&mut self) -> Result<(), PipelineError> {
+            tv::hdmi_power_on_preferred();
+            self.power = true;
+            Ok(())
+        }
+
+        fn power_off(&mut self) -> Result<(), PipelineError> {
+            tv::power_off();
+            self.power = false;
+            Ok(())
+        }
+
+        fn display_info(&mut self) -> Result<DisplayInfo, PipelineError> {
+            Ok(match self.power {
+                true => DisplayInfo {
+                    attached: true,
+                    power: true,
+                    output: Some("hdmi".to_string()),
+                    mode: Some("CEA 16".to_string()),
+                    width: 1920,
+                    height: 1080,
+                    refresh_rate: Some(60),
+                    interlaced: false,
+                },
+                false => DisplayInfo {
+                    attached: true,
+                    ..Default::default()
+                },
+            })
+        }
+
+        fn screenshot(&mut self) -> Result<RgbaImage, PipelineError> {
+            let (width, height) = self.viewport;
+            let buffer = dispmanx::snapshot(width, height)?;
+            let snapshot = RgbaImage::from_raw(width, height, buffer)
+                .ok_or(PipelineError::Assertion(Operation::SnapshotFailed))?;
+            Ok(rotate_frame(&snapshot, (360 - self.rotation) % 360).into_owned())
+        }
+
+        fn viewport(&self) -> (u32, u32) {
+            effective_viewport(self.viewport, self.rotation)
+        }
+
+        fn supports_transition(&self, kind: TransitionKind) -> bool {
+            kind != TransitionKind::Crossfade
+        }
     }
 }
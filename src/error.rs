@@ -2,28 +2,74 @@
 Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
+use std::fmt;
+
+use gotham::hyper::StatusCode;
 use serde::Serialize;
 
 #[derive(Debug)]
 pub enum PipelineError {
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     ILClientError(Operation, i32),
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     OMXError(Operation, i32),
+    ImageError(Operation, image::ImageError),
+    #[cfg(feature = "fbdev")]
+    IOError(Operation, std::io::Error),
     Assertion(Operation),
 }
 
 #[derive(Debug)]
 pub enum Operation {
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     CreateComponentFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     UnableToGetParameter,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     UnableToSetParameter,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     UnableToSetConfig,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     InvalidNumberOfPorts,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     SendCommandFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     UseBufferFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     EmptyBufferFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     FreeBufferFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     EventTimeout,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
     SetupTunnelFailed,
+    WriteFrameFailed,
+    #[cfg(feature = "fbdev")]
+    OpenDeviceFailed,
+    #[cfg(feature = "fbdev")]
+    UnsupportedPixelFormat,
+    #[cfg(feature = "fbdev")]
+    BlankFailed,
+    SnapshotFailed,
+    #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
+    DisplayStateFailed,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
+            PipelineError::ILClientError(op, code) => {
+                write!(f, "{:?}: ilclient error {}", op, code)
+            }
+            #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
+            PipelineError::OMXError(op, code) => write!(f, "{:?}: OMX error {:#x}", op, code),
+            PipelineError::ImageError(op, err) => write!(f, "{:?}: {}", op, err),
+            #[cfg(feature = "fbdev")]
+            PipelineError::IOError(op, err) => write!(f, "{:?}: {}", op, err),
+            PipelineError::Assertion(op) => write!(f, "{:?}", op),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -32,6 +78,25 @@ pub struct ImageError {
     pub image_error: image::ImageError,
 }
 
+impl ImageError {
+    pub fn status(&self) -> StatusCode {
+        match self.image_error {
+            image::ImageError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            image::ImageError::Limits(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// A request option the display cannot honour, answered with 400.
+    pub fn invalid_option(message: String) -> ImageError {
+        ImageError {
+            image_error: image::ImageError::Parameter(image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::Generic(message),
+            )),
+        }
+    }
+}
+
 fn image_error_serde<S>(image_error: &image::ImageError, s: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -39,13 +104,49 @@ where
     s.serialize_str(&format!("{:?}", image_error))
 }
 
-#[derive(Debug, Serialize)]
-pub struct NoError {}
+#[derive(Debug)]
+pub enum FetchError {
+    InvalidUrl,
+    UnsupportedScheme,
+    Timeout,
+    TooLarge,
+    TooManyRedirects,
+    NotImage(String),
+    Status(StatusCode),
+    Http(gotham::hyper::Error),
+}
 
-#[derive(Debug, Serialize)]
-pub struct HttpError<T: Serialize> {
-    pub status: u16,
-    pub reason: Option<&'static str>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub details: Option<T>,
+impl FetchError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FetchError::InvalidUrl | FetchError::UnsupportedScheme => StatusCode::BAD_REQUEST,
+            FetchError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            FetchError::NotImage(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::InvalidUrl => write!(f, "invalid URL"),
+            FetchError::UnsupportedScheme => write!(f, "only http:// URLs are supported"),
+            FetchError::Timeout => write!(f, "timed out"),
+            FetchError::TooLarge => write!(f, "response too large"),
+            FetchError::TooManyRedirects => write!(f, "too many redirects"),
+            FetchError::NotImage(content_type) => write!(f, "not an image: {}", content_type),
+            FetchError::Status(status) => write!(f, "server responded with {}", status),
+            FetchError::Http(err) => write!(f, "request failed: {}", err),
+        }
+    }
+}
+
+impl Serialize for FetchError {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.collect_str(self)
+    }
 }
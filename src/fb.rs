@@ -0,0 +1,104 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::fs::File;
+use std::io;
+use std::os::raw::c_ulong;
+use std::os::unix::io::AsRawFd;
+
+const FBIOGET_VSCREENINFO: u32 = 0x4600;
+const FBIOGET_FSCREENINFO: u32 = 0x4602;
+const FBIOBLANK: u32 = 0x4611;
+
+const FB_BLANK_UNBLANK: u32 = 0;
+const FB_BLANK_POWERDOWN: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FbBitfield {
+    pub offset: u32,
+    pub length: u32,
+    pub msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FbVarScreenInfo {
+    pub xres: u32,
+    pub yres: u32,
+    pub xres_virtual: u32,
+    pub yres_virtual: u32,
+    pub xoffset: u32,
+    pub yoffset: u32,
+    pub bits_per_pixel: u32,
+    pub grayscale: u32,
+    pub red: FbBitfield,
+    pub green: FbBitfield,
+    pub blue: FbBitfield,
+    pub transp: FbBitfield,
+    pub nonstd: u32,
+    pub activate: u32,
+    pub height: u32,
+    pub width: u32,
+    pub accel_flags: u32,
+    pub pixclock: u32,
+    pub left_margin: u32,
+    pub right_margin: u32,
+    pub upper_margin: u32,
+    pub lower_margin: u32,
+    pub hsync_len: u32,
+    pub vsync_len: u32,
+    pub sync: u32,
+    pub vmode: u32,
+    pub rotate: u32,
+    pub colorspace: u32,
+    pub reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FbFixScreenInfo {
+    pub id: [u8; 16],
+    pub smem_start: c_ulong,
+    pub smem_len: u32,
+    pub type_: u32,
+    pub type_aux: u32,
+    pub visual: u32,
+    pub xpanstep: u16,
+    pub ypanstep: u16,
+    pub ywrapstep: u16,
+    pub line_length: u32,
+    pub mmio_start: c_ulong,
+    pub mmio_len: u32,
+    pub accel: u32,
+    pub capabilities: u16,
+    pub reserved: [u16; 2],
+}
+
+pub fn get_var_screeninfo(file: &File) -> io::Result<FbVarScreenInfo> {
+    let mut info = FbVarScreenInfo::default();
+    match unsafe { libc::ioctl(file.as_raw_fd(), FBIOGET_VSCREENINFO as _, &mut info) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(info),
+    }
+}
+
+pub fn get_fix_screeninfo(file: &File) -> io::Result<FbFixScreenInfo> {
+    let mut info = FbFixScreenInfo::default();
+    match unsafe { libc::ioctl(file.as_raw_fd(), FBIOGET_FSCREENINFO as _, &mut info) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(info),
+    }
+}
+
+pub fn blank(file: &File, blank: bool) -> io::Result<()> {
+    let level = match blank {
+        true => FB_BLANK_POWERDOWN,
+        false => FB_BLANK_UNBLANK,
+    };
+    match unsafe { libc::ioctl(file.as_raw_fd(), FBIOBLANK as _, level as libc::c_ulong) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,391 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::backend::DisplayBackend;
+use crate::display::{frame::*, image::*, power::*, region::*, transition::*};
+use crate::error::{Operation, PipelineError};
+use crate::fb::{self, *};
+use image::{Rgba, RgbaImage};
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u32,
+    pub red: FbBitfield,
+    pub green: FbBitfield,
+    pub blue: FbBitfield,
+    pub transp: FbBitfield,
+    pub line_length: u32,
+    pub offset: u64,
+}
+
+fn bitfield(offset: u32, length: u32) -> FbBitfield {
+    FbBitfield {
+        offset,
+        length,
+        msb_right: 0,
+    }
+}
+
+fn pack_channel(value: u8, field: &FbBitfield) -> u32 {
+    let value = match field.length {
+        0 => return 0,
+        length if length < 8 => value as u32 >> (8 - length),
+        length => (value as u32) << (length - 8),
+    };
+    value << field.offset
+}
+
+impl PixelFormat {
+    pub fn with_bits_per_pixel(bits_per_pixel: u32, width: u32) -> Option<Self> {
+        let (red, green, blue, transp) = match bits_per_pixel {
+            16 => (
+                bitfield(11, 5),
+                bitfield(5, 6),
+                bitfield(0, 5),
+                bitfield(0, 0),
+            ),
+            24 => (
+                bitfield(16, 8),
+                bitfield(8, 8),
+                bitfield(0, 8),
+                bitfield(0, 0),
+            ),
+            32 => (
+                bitfield(16, 8),
+                bitfield(8, 8),
+                bitfield(0, 8),
+                bitfield(24, 8),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            bits_per_pixel,
+            red,
+            green,
+            blue,
+            transp,
+            line_length: width * bits_per_pixel / 8,
+            offset: 0,
+        })
+    }
+
+    pub fn with_screeninfo(var: &FbVarScreenInfo, fix: &FbFixScreenInfo) -> Option<Self> {
+        match var.bits_per_pixel {
+            16 | 24 | 32 => Some(Self {
+                bits_per_pixel: var.bits_per_pixel,
+                red: var.red,
+                green: var.green,
+                blue: var.blue,
+                transp: var.transp,
+                line_length: fix.line_length,
+                offset: var.yoffset as u64 * fix.line_length as u64
+                    + var.xoffset as u64 * var.bits_per_pixel as u64 / 8,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    pub fn pack(&self, pixel: &Rgba<u8>) -> u32 {
+        let [r, g, b, a] = pixel.0;
+        pack_channel(r, &self.red)
+            | pack_channel(g, &self.green)
+            | pack_channel(b, &self.blue)
+            | pack_channel(a, &self.transp)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Pipeline {
+    viewport: (u32, u32),
+    rotation: u32,
+    device: PathBuf,
+    bits_per_pixel: u32,
+    format: PixelFormat,
+    framebuffer: bool,
+    frame: Option<RgbaImage>,
+    base: Option<RgbaImage>,
+    regions: BTreeMap<String, (Region, RgbaImage)>,
+    power: bool,
+}
+
+impl Pipeline {
+    pub fn new(width: u32, height: u32) -> Pipeline {
+        Pipeline {
+            viewport: (width, height),
+            device: PathBuf::from("/dev/fb0"),
+            bits_per_pixel: 32,
+            power: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_device(&mut self, device: PathBuf) {
+        self.device = device;
+    }
+
+    pub fn set_rotation(&mut self, rotation: u32) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_bits_per_pixel(&mut self, bits_per_pixel: u32) {
+        self.bits_per_pixel = bits_per_pixel;
+    }
+
+    fn blank(&self, blank: bool) -> Result<(), PipelineError> {
+        if !self.framebuffer {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.device)
+            .map_err(|e| PipelineError::IOError(Operation::OpenDeviceFailed, e))?;
+        fb::blank(&file, blank).map_err(|e| PipelineError::IOError(Operation::BlankFailed, e))
+    }
+
+    fn with_regions(&self, base: &RgbaImage) -> RgbaImage {
+        let mut frame = base.clone();
+        overlay_regions(&mut frame, self.regions.values());
+        frame
+    }
+
+    fn refresh(&mut self) -> Result<(), PipelineError> {
+        let frame = match &self.base {
+            Some(base) => self.with_regions(base),
+            None => self.with_regions(&black_frame(self.viewport())),
+        };
+        self.write_frame(&frame)?;
+        self.frame = Some(frame);
+        Ok(())
+    }
+
+    fn write_frame(&self, frame: &RgbaImage) -> Result<(), PipelineError> {
+        let frame = rotate_frame(frame, self.rotation);
+        let format = &self.format;
+        let bytes = format.bytes_per_pixel();
+        let line_length = format.line_length as usize;
+        let mut buffer = vec![0u8; line_length * frame.height() as usize];
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            let at = y as usize * line_length + x as usize * bytes;
+            buffer[at..at + bytes].copy_from_slice(&format.pack(pixel).to_ne_bytes()[..bytes]);
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&self.device)
+            .map_err(|e| PipelineError::IOError(Operation::OpenDeviceFailed, e))?;
+        file.seek(SeekFrom::Start(format.offset))
+            .and_then(|_| file.write_all(&buffer))
+            .map_err(|e| PipelineError::IOError(Operation::WriteFrameFailed, e))
+    }
+}
+
+impl DisplayBackend for Pipeline {
+    fn init(&mut self) -> Result<(), PipelineError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.device)
+            .map_err(|e| PipelineError::IOError(Operation::OpenDeviceFailed, e))?;
+
+        let format = match (get_var_screeninfo(&file), get_fix_screeninfo(&file)) {
+            (Ok(var), Ok(fix)) => {
+                self.viewport = (var.xres, var.yres);
+                self.framebuffer = true;
+                PixelFormat::with_screeninfo(&var, &fix)
+            }
+            // Not a framebuffer device (e.g. a regular file); use the configured layout
+            _ => PixelFormat::with_bits_per_pixel(self.bits_per_pixel, self.viewport.0),
+        };
+
+        self.format = format.ok_or(PipelineError::Assertion(Operation::UnsupportedPixelFormat))?;
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    fn render_image(
+        &mut self,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        _transition: Transition,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let viewport = effective_viewport(self.viewport, self.rotation);
+        let base = composite(image, content_mode, viewport);
+        let frame = self.with_regions(&base);
+        self.base = Some(base);
+
+        self.write_frame(&frame)?;
+        self.frame = Some(frame);
+
+        Ok(())
+    }
+
+    fn render_region(
+        &mut self,
+        name: &str,
+        region: Region,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let content = composite(image, content_mode, region.size());
+        self.regions.insert(name.to_string(), (region, content));
+        self.refresh()
+    }
+
+    fn remove_region(&mut self, name: &str) -> Result<(), PipelineError> {
+        if self.regions.remove(name).is_some() {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn power_on(&mut self) -> Result<(), PipelineError> {
+        self.blank(false)?;
+        self.power = true;
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), PipelineError> {
+        self.blank(true)?;
+        self.power = false;
+        Ok(())
+    }
+
+    fn display_info(&mut self) -> Result<DisplayInfo, PipelineError> {
+        let (width, height) = self.viewport;
+        Ok(DisplayInfo {
+            attached: true,
+            power: self.power,
+            output: self.framebuffer.then(|| "fbdev".to_string()),
+            width,
+            height,
+            ..Default::default()
+        })
+    }
+
+    fn screenshot(&mut self) -> Result<RgbaImage, PipelineError> {
+        Ok(match &self.frame {
+            Some(frame) => frame.clone(),
+            None => black_frame(self.viewport()),
+        })
+    }
+
+    fn viewport(&self) -> (u32, u32) {
+        effective_viewport(self.viewport, self.rotation)
+    }
+
+    fn frame(&self) -> Option<RgbaImage> {
+        self.frame.clone()
+    }
+
+    fn compose(&self, image: &DisplayImage, content_mode: ContentMode) -> RgbaImage {
+        self.with_regions(&composite(image, content_mode, self.viewport()))
+    }
+
+    fn show_frame(&mut self, frame: &RgbaImage) -> Result<(), PipelineError> {
+        self.write_frame(frame)?;
+        self.frame = Some(frame.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageFormat;
+    use tempfile::NamedTempFile;
+
+    fn render(pipeline: &mut Pipeline, img: RgbaImage) {
+        let image = DisplayImage::new(img, 0, ImageFormat::Png);
+        pipeline
+            .render_image(&image, ContentMode::ScaleToFill, Transition::default(), 0)
+            .unwrap();
+    }
+
+    // red, green / blue, white
+    fn quad() -> RgbaImage {
+        RgbaImage::from_fn(2, 2, |x, y| match (x, y) {
+            (0, 0) => Rgba([0xff, 0, 0, 0xff]),
+            (1, 0) => Rgba([0, 0xff, 0, 0xff]),
+            (0, 1) => Rgba([0, 0, 0xff, 0xff]),
+            _ => Rgba([0xff, 0xff, 0xff, 0xff]),
+        })
+    }
+
+    fn pixels(values: &[u32], bytes: usize) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_ne_bytes()[..bytes].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn packs_rgb565() {
+        let format = PixelFormat::with_bits_per_pixel(16, 2).unwrap();
+        assert_eq!(format.line_length, 4);
+        assert_eq!(format.pack(&Rgba([0xff, 0, 0, 0xff])), 0xf800);
+        assert_eq!(format.pack(&Rgba([0, 0xff, 0, 0xff])), 0x07e0);
+        assert_eq!(format.pack(&Rgba([0, 0, 0xff, 0xff])), 0x001f);
+        assert_eq!(format.pack(&Rgba([0x80, 0x80, 0x80, 0xff])), 0x8410);
+    }
+
+    #[test]
+    fn writes_16bpp_frame() {
+        let file = NamedTempFile::new().unwrap();
+        let mut pipeline = Pipeline::new(2, 2);
+        pipeline.set_device(file.path().to_path_buf());
+        pipeline.set_bits_per_pixel(16);
+        pipeline.init().unwrap();
+        render(&mut pipeline, quad());
+
+        let written = std::fs::read(file.path()).unwrap();
+        assert_eq!(written, pixels(&[0xf800, 0x07e0, 0x001f, 0xffff], 2));
+    }
+
+    #[test]
+    fn writes_32bpp_frame() {
+        let file = NamedTempFile::new().unwrap();
+        let mut pipeline = Pipeline::new(2, 2);
+        pipeline.set_device(file.path().to_path_buf());
+        pipeline.init().unwrap();
+        render(&mut pipeline, quad());
+
+        let written = std::fs::read(file.path()).unwrap();
+        let expected = [0xffff0000, 0xff00ff00, 0xff0000ff, 0xffffffff];
+        assert_eq!(written, pixels(&expected, 4));
+    }
+
+    #[test]
+    fn pads_rows_to_line_length() {
+        let file = NamedTempFile::new().unwrap();
+        let mut pipeline = Pipeline::new(2, 2);
+        pipeline.set_device(file.path().to_path_buf());
+        pipeline.set_bits_per_pixel(24);
+        pipeline.init().unwrap();
+        // 2 pixels of 3 bytes in an 8 byte stride
+        pipeline.format.line_length = 8;
+        render(&mut pipeline, quad());
+
+        let written = std::fs::read(file.path()).unwrap();
+        assert_eq!(written.len(), 16);
+        assert_eq!(written[0..6], pixels(&[0xff0000, 0x00ff00], 3)[..]);
+        assert_eq!(written[6..8], [0, 0]);
+        assert_eq!(written[8..14], pixels(&[0x0000ff, 0xffffff], 3)[..]);
+        assert_eq!(written[14..16], [0, 0]);
+    }
+}
@@ -0,0 +1,216 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::time::Duration;
+
+use gotham::hyper::body::{Bytes, HttpBody};
+use gotham::hyper::{header, Body, Client, Response, Uri};
+
+use crate::error::FetchError;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_FETCH_SIZE: usize = 32 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+
+/// Downloads `url` over plain HTTP, returning the body and its content type.
+pub async fn fetch(url: &str) -> Result<(Bytes, Option<String>), FetchError> {
+    fetch_with_limits(url, FETCH_TIMEOUT, MAX_FETCH_SIZE).await
+}
+
+async fn fetch_with_limits(
+    url: &str,
+    timeout: Duration,
+    max_size: usize,
+) -> Result<(Bytes, Option<String>), FetchError> {
+    let uri: Uri = url.parse().map_err(|_| FetchError::InvalidUrl)?;
+    check_scheme(&uri)?;
+
+    tokio::time::timeout(timeout, get(uri, max_size))
+        .await
+        .map_err(|_| FetchError::Timeout)?
+}
+
+fn check_scheme(uri: &Uri) -> Result<(), FetchError> {
+    match uri.scheme_str() {
+        Some("http") if uri.host().is_some() => Ok(()),
+        Some("http") | None => Err(FetchError::InvalidUrl),
+        Some(_) => Err(FetchError::UnsupportedScheme),
+    }
+}
+
+/// Follows up to `MAX_REDIRECTS` redirects, as long as they stay on plain HTTP.
+async fn follow(mut uri: Uri) -> Result<Response<Body>, FetchError> {
+    let client = Client::new();
+    for _ in 0..=MAX_REDIRECTS {
+        let resp = client.get(uri.clone()).await.map_err(FetchError::Http)?;
+        if !resp.status().is_redirection() {
+            return Ok(resp);
+        }
+        let location = resp
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(FetchError::Status(resp.status()))?;
+        uri = resolve(&uri, location)?;
+        check_scheme(&uri)?;
+    }
+    Err(FetchError::TooManyRedirects)
+}
+
+/// Resolves a `Location` header, which may be a path on the same server.
+fn resolve(base: &Uri, location: &str) -> Result<Uri, FetchError> {
+    let uri: Uri = location.parse().map_err(|_| FetchError::InvalidUrl)?;
+    if uri.scheme().is_some() {
+        return Ok(uri);
+    }
+    let mut parts = uri.into_parts();
+    parts.scheme = base.scheme().cloned();
+    parts.authority = base.authority().cloned();
+    Uri::from_parts(parts).map_err(|_| FetchError::InvalidUrl)
+}
+
+async fn get(uri: Uri, max_size: usize) -> Result<(Bytes, Option<String>), FetchError> {
+    let resp = follow(uri).await?;
+    if !resp.status().is_success() {
+        return Err(FetchError::Status(resp.status()));
+    }
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase());
+    // Without a usable content type the format is guessed from the body
+    let content_type = match content_type {
+        Some(value) if value.starts_with("image/") => Some(value),
+        Some(value) if value.is_empty() || value == "application/octet-stream" => None,
+        Some(value) => return Err(FetchError::NotImage(value)),
+        None => None,
+    };
+
+    let mut body = resp.into_body();
+    if body.size_hint().lower() as usize > max_size {
+        return Err(FetchError::TooLarge);
+    }
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(FetchError::Http)?;
+        if buffer.len() + chunk.len() > max_size {
+            return Err(FetchError::TooLarge);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    Ok((buffer.into(), content_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gotham::hyper::service::{make_service_fn, service_fn};
+    use gotham::hyper::{Request, Server, StatusCode};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    /// Serves a few fixed responses on an ephemeral port.
+    async fn serve() -> SocketAddr {
+        async fn respond(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+            let resp = Response::builder();
+            Ok(match req.uri().path() {
+                "/image" => resp
+                    .header(header::CONTENT_TYPE, "image/png; charset=binary")
+                    .body(Body::from(vec![0u8; 16])),
+                "/large" => resp
+                    .header(header::CONTENT_TYPE, "image/png")
+                    .body(Body::from(vec![0u8; 1024])),
+                "/html" => resp
+                    .header(header::CONTENT_TYPE, "text/html")
+                    .body(Body::from("<html></html>")),
+                "/slow" => {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    resp.body(Body::empty())
+                }
+                "/redirect" => resp
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, "/image")
+                    .body(Body::empty()),
+                "/loop" => resp
+                    .status(StatusCode::FOUND)
+                    .header(header::LOCATION, "/loop")
+                    .body(Body::empty()),
+                "/https" => resp
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(header::LOCATION, "https://example.com/image")
+                    .body(Body::empty()),
+                _ => resp.status(StatusCode::NOT_FOUND).body(Body::empty()),
+            }
+            .unwrap())
+        }
+
+        let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(respond)) });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn fetch_path(
+        addr: SocketAddr,
+        path: &str,
+    ) -> Result<(Bytes, Option<String>), FetchError> {
+        let url = format!("http://{}{}", addr, path);
+        fetch_with_limits(&url, Duration::from_millis(500), 512).await
+    }
+
+    #[tokio::test]
+    async fn fetches_image() {
+        let addr = serve().await;
+        let (body, content_type) = fetch_path(addr, "/image").await.unwrap();
+        assert_eq!(body.len(), 16);
+        assert_eq!(content_type.as_deref(), Some("image/png"));
+    }
+
+    #[tokio::test]
+    async fn rejects_large_response() {
+        let addr = serve().await;
+        let err = fetch_path(addr, "/large").await.unwrap_err();
+        assert!(matches!(err, FetchError::TooLarge));
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let addr = serve().await;
+        let err = fetch_path(addr, "/slow").await.unwrap_err();
+        assert!(matches!(err, FetchError::Timeout));
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn rejects_non_image_content_type() {
+        let addr = serve().await;
+        let err = fetch_path(addr, "/html").await.unwrap_err();
+        assert!(matches!(&err, FetchError::NotImage(value) if value == "text/html"));
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn follows_redirects() {
+        let addr = serve().await;
+        let (body, _) = fetch_path(addr, "/redirect").await.unwrap();
+        assert_eq!(body.len(), 16);
+
+        let err = fetch_path(addr, "/loop").await.unwrap_err();
+        assert!(matches!(err, FetchError::TooManyRedirects));
+        let err = fetch_path(addr, "/https").await.unwrap_err();
+        assert!(matches!(err, FetchError::UnsupportedScheme));
+    }
+
+    #[tokio::test]
+    async fn rejects_https() {
+        let err = fetch("https://example.com/image.png").await.unwrap_err();
+        assert!(matches!(err, FetchError::UnsupportedScheme));
+        assert_eq!(err.to_string(), "only http:// URLs are supported");
+    }
+}
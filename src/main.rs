@@ -3,42 +3,223 @@ Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
 mod api;
+mod backend;
+mod backlight;
+mod cec;
 #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
 mod component;
+mod directory;
 mod display;
 mod error;
+#[cfg(all(target_os = "linux", feature = "fbdev"))]
+mod fb;
+#[cfg(all(target_os = "linux", feature = "fbdev"))]
+mod fbdev;
+mod fetch;
 #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
 mod pipeline;
+mod regions;
+mod schedule;
+mod slideshow;
+#[cfg(feature = "software")]
+mod software;
+mod store;
 #[cfg(all(target_os = "linux", feature = "raspberry-pi"))]
 mod vc;
+mod wakeup;
 
 #[cfg(not(all(target_os = "linux", feature = "raspberry-pi")))]
 mod dummy;
 #[cfg(not(all(target_os = "linux", feature = "raspberry-pi")))]
 use dummy::{pipeline, vc};
 
-use pipeline::*;
+use backend::*;
+use backlight::*;
+use display::{image::ContentMode, transition::Transition};
+use regions::*;
+use schedule::*;
+use slideshow::*;
+use store::*;
 use vc::*;
 
 use futures::prelude::*;
 use getopts::Options;
 use std::env;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::process::exit;
 
+#[cfg(any(feature = "software", all(target_os = "linux", feature = "fbdev")))]
+const DEFAULT_SIZE: (u32, u32) = (1920, 1080);
+
+#[derive(Debug, PartialEq)]
+enum BackendKind {
+    Omx,
+    #[cfg(feature = "software")]
+    Software,
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    Fbdev,
+}
+
+impl BackendKind {
+    fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "omx" => Some(BackendKind::Omx),
+            #[cfg(feature = "software")]
+            "software" => Some(BackendKind::Software),
+            #[cfg(all(target_os = "linux", feature = "fbdev"))]
+            "fbdev" => Some(BackendKind::Fbdev),
+            _ => None,
+        }
+    }
+
+    fn names() -> Vec<&'static str> {
+        let mut names = vec!["omx"];
+        if cfg!(feature = "software") {
+            names.push("software");
+        }
+        if cfg!(all(target_os = "linux", feature = "fbdev")) {
+            names.push("fbdev");
+        }
+        names
+    }
+}
+
+struct Config {
+    addr: SocketAddr,
+    backend: BackendKind,
+    interval: u64,
+    dir: Option<PathBuf>,
+    shuffle: bool,
+    content_mode: ContentMode,
+    transition: Transition,
+    state_dir: PathBuf,
+    restore: bool,
+    display_rotation: u32,
+    max_image_size: Option<(u32, u32)>,
+    backlight_dir: PathBuf,
+    backlight: Option<String>,
+    #[cfg(target_os = "linux")]
+    cec: Option<PathBuf>,
+    #[cfg(feature = "software")]
+    output: Option<PathBuf>,
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    device: Option<PathBuf>,
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    bits_per_pixel: Option<u32>,
+    #[cfg(any(feature = "software", all(target_os = "linux", feature = "fbdev")))]
+    size: Option<(u32, u32)>,
+}
+
 async fn shutdown_signal() {
     tokio::signal::ctrl_c()
         .await
         .expect("Failed to install signal handler");
 }
 
-fn parse_opts() -> SocketAddr {
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let mut size = size.splitn(2, 'x');
+    let width = size.next()?.parse().ok()?;
+    let height = size.next()?.parse().ok()?;
+    Some((width, height))
+}
+
+fn parse_opts() -> Config {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optopt("H", "host", "address to bind (default: 127.0.0.1)", "ADDR");
     opts.optopt("P", "port", "port to listen (default: 3000)", "NUM");
+    opts.optopt(
+        "b",
+        "backend",
+        &format!(
+            "display backend: {} (default: omx)",
+            BackendKind::names().join(", ")
+        ),
+        "NAME",
+    );
+    #[cfg(feature = "software")]
+    opts.optopt("o", "output", "write rendered frames to PNG file", "PATH");
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    opts.optopt(
+        "d",
+        "device",
+        "framebuffer device (default: /dev/fb0)",
+        "PATH",
+    );
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    opts.optopt(
+        "",
+        "bpp",
+        "bits per pixel if device is not a framebuffer (default: 32)",
+        "NUM",
+    );
+    #[cfg(any(feature = "software", all(target_os = "linux", feature = "fbdev")))]
+    opts.optopt("s", "size", "viewport size (default: 1920x1080)", "WxH");
+    opts.optopt(
+        "i",
+        "interval",
+        "slideshow interval in seconds (default: 30)",
+        "SECS",
+    );
+    opts.optopt("", "dir", "show images in directory as slideshow", "PATH");
+    opts.optflag("", "shuffle", "shuffle images in directory");
+    opts.optopt(
+        "",
+        "mode",
+        "content mode of images in directory (default: aspect_fit)",
+        "MODE",
+    );
+    opts.optopt(
+        "",
+        "transition",
+        "transition between images in directory: crossfade, fade, slide (default: none)",
+        "NAME",
+    );
+    opts.optopt(
+        "",
+        "state-dir",
+        "directory to save the displayed image and power schedule (default: /var/lib/dpf-pi)",
+        "PATH",
+    );
+    opts.optflag(
+        "",
+        "no-restore",
+        "do not save and restore the displayed image",
+    );
+    opts.optopt(
+        "",
+        "display-rotation",
+        "rotate the whole display clockwise: 0, 90, 180, 270 (default: 0)",
+        "DEGREES",
+    );
+    opts.optopt(
+        "",
+        "max-image-size",
+        "downscale larger images while decoding (default: viewport size)",
+        "WxH",
+    );
+    opts.optopt(
+        "",
+        "backlight-dir",
+        "directory of sysfs backlight devices (default: /sys/class/backlight)",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "backlight",
+        "backlight device to control (default: first one found)",
+        "NAME",
+    );
+    #[cfg(target_os = "linux")]
+    opts.optopt(
+        "",
+        "cec",
+        "control the TV over HDMI-CEC with this device, e.g. /dev/cec0",
+        "PATH",
+    );
     opts.optflag("h", "help", "print this help");
 
     let matches = opts.parse(&args[1..]).unwrap_or_else(|e| {
@@ -59,33 +240,279 @@ fn parse_opts() -> SocketAddr {
     let addr = matches.opt_str("H").unwrap_or("127.0.0.1".to_string());
     let port = matches.opt_str("P").unwrap_or("3000".to_string());
 
-    format!("{}:{}", addr, port)
+    let addr = format!("{}:{}", addr, port)
         .parse()
         .unwrap_or(SocketAddr::new(
             IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             3000,
-        ))
+        ));
+
+    let backend = matches.opt_str("b").unwrap_or("omx".to_string());
+    let backend = BackendKind::from_str(&backend).unwrap_or_else(|| {
+        eprintln!("Unknown backend: {}", backend);
+        exit(1);
+    });
+
+    let interval = matches.opt_str("i").map_or(30, |interval| {
+        interval.parse().ok().filter(|&i| i > 0).unwrap_or_else(|| {
+            eprintln!("Invalid interval: {}", interval);
+            exit(1);
+        })
+    });
+
+    let dir = matches.opt_str("dir").map(PathBuf::from);
+    let shuffle = matches.opt_present("shuffle");
+    let content_mode =
+        ContentMode::from_str(&matches.opt_str("mode").unwrap_or("aspect_fit".to_string()));
+    let transition = Transition::from_str(&matches.opt_str("transition").unwrap_or_default(), None);
+    let state_dir = PathBuf::from(
+        matches
+            .opt_str("state-dir")
+            .unwrap_or("/var/lib/dpf-pi".to_string()),
+    );
+    let restore = !matches.opt_present("no-restore");
+    let display_rotation = matches.opt_str("display-rotation").map_or(0, |rotation| {
+        rotation
+            .parse()
+            .ok()
+            .filter(|r| [0, 90, 180, 270].contains(r))
+            .unwrap_or_else(|| {
+                eprintln!("Invalid display rotation: {}", rotation);
+                exit(1);
+            })
+    });
+    let max_image_size = matches.opt_str("max-image-size").map(|size| {
+        parse_size(&size).unwrap_or_else(|| {
+            eprintln!("Invalid max image size: {}", size);
+            exit(1);
+        })
+    });
+
+    let backlight_dir = PathBuf::from(
+        matches
+            .opt_str("backlight-dir")
+            .unwrap_or("/sys/class/backlight".to_string()),
+    );
+    let backlight = matches.opt_str("backlight");
+    #[cfg(target_os = "linux")]
+    let cec = matches.opt_str("cec").map(PathBuf::from);
+
+    #[cfg(feature = "software")]
+    let output = matches.opt_str("o").map(PathBuf::from);
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    let device = matches.opt_str("d").map(PathBuf::from);
+    #[cfg(all(target_os = "linux", feature = "fbdev"))]
+    let bits_per_pixel = matches.opt_str("bpp").map(|bpp| {
+        bpp.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid bits per pixel: {}", bpp);
+            exit(1);
+        })
+    });
+    #[cfg(any(feature = "software", all(target_os = "linux", feature = "fbdev")))]
+    let size = matches.opt_str("s").map(|size| {
+        parse_size(&size).unwrap_or_else(|| {
+            eprintln!("Invalid size: {}", size);
+            exit(1);
+        })
+    });
+
+    Config {
+        addr,
+        backend,
+        interval,
+        dir,
+        shuffle,
+        content_mode,
+        transition,
+        state_dir,
+        restore,
+        display_rotation,
+        max_image_size,
+        backlight_dir,
+        backlight,
+        #[cfg(target_os = "linux")]
+        cec,
+        #[cfg(feature = "software")]
+        output,
+        #[cfg(all(target_os = "linux", feature = "fbdev"))]
+        device,
+        #[cfg(all(target_os = "linux", feature = "fbdev"))]
+        bits_per_pixel,
+        #[cfg(any(feature = "software", all(target_os = "linux", feature = "fbdev")))]
+        size,
+    }
+}
+
+fn create_backend(config: Config) -> Backend {
+    match config.backend {
+        BackendKind::Omx => {
+            omx::init();
+            let (width, height) = omx::get_display_size(0);
+            let mut pipeline = pipeline::Pipeline::new(width, height);
+            pipeline.set_rotation(config.display_rotation);
+            Backend::new(pipeline)
+        }
+        #[cfg(feature = "software")]
+        BackendKind::Software => {
+            let (width, height) = config.size.unwrap_or(DEFAULT_SIZE);
+            let mut pipeline = software::Pipeline::new(width, height);
+            pipeline.set_rotation(config.display_rotation);
+            pipeline.set_output(config.output);
+            Backend::new(pipeline)
+        }
+        #[cfg(all(target_os = "linux", feature = "fbdev"))]
+        BackendKind::Fbdev => {
+            let (width, height) = config.size.unwrap_or(DEFAULT_SIZE);
+            let mut pipeline = fbdev::Pipeline::new(width, height);
+            pipeline.set_rotation(config.display_rotation);
+            if let Some(device) = config.device {
+                pipeline.set_device(device);
+            }
+            if let Some(bits_per_pixel) = config.bits_per_pixel {
+                pipeline.set_bits_per_pixel(bits_per_pixel);
+            }
+            Backend::new(pipeline)
+        }
+    }
+}
+
+fn restore_image(backend: &Backend, store: &ImageStore) {
+    let stored = match store.load() {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return,
+        Err(err) => {
+            log::warn!("Failed to load saved image: {:?}", err);
+            return;
+        }
+    };
+    let max_size = backend.max_image_size();
+    let mut image = match api::load_image(stored.body.clone(), stored.format.as_deref(), max_size) {
+        Ok(image) => image,
+        Err(err) => {
+            log::warn!("Failed to decode saved image: {:?}", err);
+            return;
+        }
+    };
+    image.set_orientation(stored.orientation);
+    image.set_background(stored.background);
+    image.set_focus(stored.focus);
+    if let Some(crop) = stored.crop {
+        image.set_crop(crop);
+    }
+    image.set_title(stored.title);
+    if let Err(err) = backend.render(
+        &image,
+        stored.content_mode,
+        Transition::default(),
+        RENDER_TIMEOUT,
+    ) {
+        log::warn!("Failed to render saved image: {:?}", err);
+        return;
+    }
+    store.set_current(&stored.body, &image, stored.content_mode);
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = parse_opts();
+    let config = parse_opts();
+    let addr = config.addr;
+    let omx = config.backend == BackendKind::Omx;
+    let interval = config.interval;
+    let (dir, shuffle) = (config.dir.clone(), config.shuffle);
+    let (content_mode, transition) = (config.content_mode, config.transition);
+    let store = ImageStore::new(match config.restore {
+        true => Some(config.state_dir.clone()),
+        false => None,
+    });
+    let state_dir = config.state_dir.clone();
+    let backlight = match Backlight::discover(&config.backlight_dir, config.backlight.as_deref()) {
+        Ok(backlight) => backlight,
+        Err(err) if config.backlight.is_some() => {
+            eprintln!("Failed to open backlight: {}", err);
+            exit(1);
+        }
+        Err(err) => {
+            eprintln!("Failed to read backlights, continuing without: {}", err);
+            Backlight::default()
+        }
+    };
+    if let Some(device) = backlight.device() {
+        println!("Using backlight {:?}", device);
+    }
+    let max_image_size = config.max_image_size;
+    #[cfg(target_os = "linux")]
+    let cec = config.cec.clone();
 
     env_logger::init();
 
-    omx::init();
+    let mut backend = create_backend(config);
+    if dir.is_some() && !backend.supports_transition(transition) {
+        eprintln!(
+            "Unsupported transition for this backend: {:?}",
+            transition.kind
+        );
+        exit(1);
+    }
+    backend.lock().init().unwrap();
+    backend.set_max_image_size(max_image_size);
+    #[cfg(target_os = "linux")]
+    if let Some(path) = cec {
+        let transport = cec::device::CecDeviceTransport::open(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to open CEC device {:?}: {}", path, err);
+            exit(1);
+        });
+        backend.set_cec(cec::Cec::new(transport));
+    }
+
+    let slideshow = Slideshow::new(backend.clone(), store.clone(), interval);
+    tokio::spawn(slideshow.clone().run());
+
+    if dir.is_none() {
+        restore_image(&backend, &store);
+    }
+
+    if let Some(dir) = dir {
+        let count = directory::scan(&dir, &slideshow, content_mode, transition)?;
+        println!("Found {} images in {:?}", count, dir);
+        slideshow.set_shuffle(shuffle);
+        slideshow.play();
+
+        let slideshow = slideshow.clone();
+        tokio::spawn(async move {
+            if let Err(err) = directory::watch(dir, slideshow, content_mode, transition).await {
+                log::error!("Failed to watch directory: {:?}", err);
+            }
+        });
+    }
+
+    tokio::spawn(backend.clone().run_clock());
 
-    let (width, height) = omx::get_display_size(0);
-    let mut pipeline = Pipeline::new(width, height);
-    pipeline.init().unwrap();
+    let scheduler = Scheduler::new(backend.clone(), state_dir);
+    if let Err(err) = scheduler.load() {
+        log::warn!("Failed to load power schedule: {:?}", err);
+    }
+    tokio::spawn(scheduler.clone().run());
 
-    let server = gotham::init_server(addr, crate::api::router(pipeline));
+    let regions = Regions::new(backend.clone());
+    let server = gotham::init_server(
+        addr,
+        crate::api::router(
+            backend.clone(),
+            slideshow,
+            store,
+            regions,
+            scheduler,
+            backlight,
+        ),
+    );
 
     println!("Listening on http://{}", addr);
     future::select(server.boxed(), shutdown_signal().boxed()).await;
 
-    pipeline.destroy().unwrap();
-    omx::deinit();
+    backend.lock().destroy().unwrap();
+    if omx {
+        omx::deinit();
+    }
     println!("See you!");
     Ok(())
 }
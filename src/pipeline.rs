@@ -2,21 +2,77 @@
 Copyright (c) 2021, Yuki MIZUNO
 SPDX-License-Identifier: BSD-3-Clause
 */
+use std::collections::BTreeMap;
 use std::mem::size_of;
 
+use crate::backend::DisplayBackend;
 use crate::component::*;
-use crate::display::{image::*, rect::*};
+use crate::display::{
+    background::*, frame::*, image::*, orientation::*, power::*, rect::*, region::*, transition::*,
+};
 use crate::error::{Operation, PipelineError};
 use crate::vc::*;
-use gotham_derive::*;
+use image::RgbaImage;
 
-#[derive(Debug, Default, Copy, Clone, StateData)]
+#[derive(Debug, Default, Clone)]
 pub struct Pipeline {
     client: i32,
     buffer_header: i32,
     render: Component,
     resize: Component,
     viewport: (u32, u32),
+    rotation: u32,
+    rect: OMX_DISPLAYRECTTYPE,
+    transform: OMX_DISPLAYTRANSFORMTYPE,
+    shown: bool,
+    layer: i32,
+    region: Option<Region>,
+    // Each region is rendered by its own resize and video_render pair on a higher layer
+    regions: BTreeMap<String, Pipeline>,
+}
+
+fn display_transform(orientation: Orientation) -> OMX_DISPLAYTRANSFORMTYPE {
+    match (orientation.rotate, orientation.mirror) {
+        (90, false) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_ROT90,
+        (180, false) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_ROT180,
+        (270, false) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_ROT270,
+        (0, true) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_MIRROR_ROT0,
+        (90, true) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_MIRROR_ROT90,
+        (180, true) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_MIRROR_ROT180,
+        (270, true) => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_MIRROR_ROT270,
+        _ => OMX_DISPLAYTRANSFORMTYPE_OMX_DISPLAY_ROT0,
+    }
+}
+
+fn transition_region(
+    transition: Transition,
+    rect: OMX_DISPLAYRECTTYPE,
+    width: u32,
+    progress: f32,
+    incoming: bool,
+) -> (OMX_DISPLAYRECTTYPE, u8) {
+    let visibility = match incoming {
+        true => progress,
+        false => 1f32 - progress,
+    };
+    match transition.kind {
+        TransitionKind::None => (rect, 0xff),
+        TransitionKind::Slide => {
+            let offset = match incoming {
+                true => (width as f32 * (1f32 - progress)) as i16,
+                false => -(width as f32 * progress) as i16,
+            };
+            let rect = OMX_DISPLAYRECTTYPE {
+                x_offset: rect.x_offset + offset,
+                ..rect
+            };
+            (rect, 0xff)
+        }
+        // Crossfade is rejected by supports_transition
+        TransitionKind::Crossfade | TransitionKind::FadeThroughBlack => {
+            (rect, (visibility * 255f32) as u8)
+        }
+    }
 }
 
 impl Pipeline {
@@ -27,7 +83,154 @@ impl Pipeline {
         }
     }
 
-    pub fn init(&mut self) -> Result<(), PipelineError> {
+    pub fn set_rotation(&mut self, rotation: u32) {
+        self.rotation = rotation;
+    }
+
+    fn region_pipeline(&self, region: Region) -> Pipeline {
+        Pipeline {
+            viewport: self.viewport,
+            rotation: self.rotation,
+            ..Default::default()
+        }
+        .with_region(region)
+    }
+
+    fn with_region(self, region: Region) -> Pipeline {
+        Pipeline {
+            layer: region.layer,
+            region: Some(region),
+            ..self
+        }
+    }
+
+    /// Viewport of the whole display, regardless of the region.
+    fn screen(&self) -> (u32, u32) {
+        effective_viewport(self.viewport, self.rotation)
+    }
+
+    fn setup(&mut self) -> Result<(), PipelineError> {
+        self.resize.set_state(State::Idle);
+        self.render.set_state(State::Idle);
+
+        self.resize.disable_port(Direction::In)?;
+        self.resize.disable_port(Direction::Out)?;
+        self.render.disable_port(Direction::In)?;
+
+        Ok(())
+    }
+
+    fn cleanup_image(&mut self) -> Result<(), PipelineError> {
+        omx::free_buffer(
+            self.resize.handle(),
+            self.resize.in_port,
+            self.buffer_header as *mut _,
+        )
+    }
+
+    pub fn prepare_image(&mut self, image: &DisplayImage) -> Result<(), PipelineError> {
+        self.resize.set_state(State::Idle);
+
+        self.resize.set_image_size(
+            Direction::In,
+            image.width(),
+            image.height(),
+            Some(image.len()),
+        )?;
+        self.resize.enable_port(Direction::In)?;
+
+        let mut buffer_header: *mut OMX_BUFFERHEADERTYPE = &mut Default::default();
+
+        omx::use_buffer(
+            self.resize.handle(),
+            &mut buffer_header,
+            self.resize.in_port,
+            std::ptr::null_mut(),
+            image.len(),
+            image.as_raw().as_ptr() as *mut _,
+        )?;
+
+        self.resize.set_state(State::Executing);
+
+        unsafe {
+            (*buffer_header).nFilledLen = image.len();
+            (*buffer_header).nFlags = OMX_BUFFERFLAG_EOS;
+            self.buffer_header = buffer_header as i32;
+        }
+        Ok(())
+    }
+
+    pub fn set_image_config(
+        &mut self,
+        display_rect: Option<OMX_DISPLAYRECTTYPE>,
+        alpha: u8,
+    ) -> Result<(), PipelineError> {
+        let (dx, dy, alpha) = match self.region {
+            Some(region) => (region.x, region.y, (alpha as f32 * region.opacity) as u8),
+            None => (0, 0, alpha),
+        };
+        let display_rect = display_rect.map(|rect| {
+            let rect = DisplayRect {
+                x: rect.x_offset + dx,
+                y: rect.y_offset + dy,
+                w: rect.width,
+                h: rect.height,
+            };
+            let DisplayRect { x, y, w, h } = rect.rotate(self.rotation, self.screen());
+            OMX_DISPLAYRECTTYPE {
+                x_offset: x,
+                y_offset: y,
+                width: w,
+                height: h,
+            }
+        });
+        self.render.set_display_region(
+            Direction::In,
+            display_rect,
+            self.transform,
+            alpha,
+            self.layer,
+        )
+    }
+
+    pub fn image_rect(
+        &self,
+        content_mode: ContentMode,
+        image: &DisplayImage,
+    ) -> OMX_DISPLAYRECTTYPE {
+        let DisplayRect { x, y, w, h } = DisplayRect::new_with_mode(
+            content_mode,
+            self.viewport(),
+            image.size(),
+            image.orientation(),
+            image.focus(),
+        );
+        OMX_DISPLAYRECTTYPE {
+            x_offset: x,
+            y_offset: y,
+            width: w,
+            height: h,
+        }
+    }
+
+    fn animate(
+        &mut self,
+        transition: Transition,
+        rect: OMX_DISPLAYRECTTYPE,
+        incoming: bool,
+    ) -> Result<(), PipelineError> {
+        let width = self.viewport().0;
+        transition.each_frame(|progress| {
+            let (rect, alpha) = transition_region(transition, rect, width, progress, incoming);
+            self.set_image_config(Some(rect), alpha)
+        })?;
+        let (rect, alpha) = transition_region(transition, rect, width, 1f32, incoming);
+        self.set_image_config(Some(rect), alpha)
+    }
+}
+
+impl DisplayBackend for Pipeline {
+    fn init(&mut self) -> Result<(), PipelineError> {
         self.client = ilclient::init() as i32;
         let mut port = OMX_PORT_PARAM_TYPE {
             nSize: size_of::<OMX_PORT_PARAM_TYPE>() as u32,
@@ -73,9 +276,13 @@ impl Pipeline {
         Ok(())
     }
 
-    pub fn destroy(&mut self) -> Result<(), PipelineError> {
+    fn destroy(&mut self) -> Result<(), PipelineError> {
         let timeout = 1000i32;
 
+        for (_, mut region) in std::mem::take(&mut self.regions) {
+            region.destroy()?;
+        }
+
         let _ = omx::free_buffer(
             self.resize.handle(),
             self.resize.in_port,
@@ -143,88 +350,41 @@ impl Pipeline {
         Ok(())
     }
 
-    fn setup(&mut self) -> Result<(), PipelineError> {
-        self.resize.set_state(State::Idle);
-        self.render.set_state(State::Idle);
-
-        self.resize.disable_port(Direction::In)?;
-        self.resize.disable_port(Direction::Out)?;
-        self.render.disable_port(Direction::In)?;
-
-        Ok(())
-    }
-
-    fn cleanup_image(&mut self) -> Result<(), PipelineError> {
-        omx::free_buffer(
-            self.resize.handle(),
-            self.resize.in_port,
-            self.buffer_header as *mut _,
-        )
-    }
-
-    pub fn prepare_image(&mut self, image: &DisplayImage) -> Result<(), PipelineError> {
-        self.resize.set_state(State::Idle);
-
-        self.resize.set_image_size(
-            Direction::In,
-            image.width(),
-            image.height(),
-            Some(image.len()),
-        )?;
-        self.resize.enable_port(Direction::In)?;
-
-        let mut buffer_header: *mut OMX_BUFFERHEADERTYPE = &mut Default::default();
-
-        omx::use_buffer(
-            self.resize.handle(),
-            &mut buffer_header,
-            self.resize.in_port,
-            std::ptr::null_mut(),
-            image.len(),
-            image.as_raw().as_ptr() as *mut _,
-        )?;
-
-        self.resize.set_state(State::Executing);
-
-        unsafe {
-            (*buffer_header).nFilledLen = image.len();
-            (*buffer_header).nFlags = OMX_BUFFERFLAG_EOS;
-            self.buffer_header = buffer_header as i32;
-        }
-        Ok(())
-    }
-
-    pub fn set_image_config(
-        &mut self,
-        display_rect: Option<OMX_DISPLAYRECTTYPE>,
-    ) -> Result<(), PipelineError> {
-        self.render.set_display_region(Direction::In, display_rect)
-    }
-
-    pub fn set_image_scale(
-        &mut self,
-        content_mode: ContentMode,
-        image: &DisplayImage,
-    ) -> Result<(), PipelineError> {
-        let DisplayRect { x, y, w, h } =
-            DisplayRect::new_with_mode(content_mode, self.viewport, image.size());
-        self.set_image_config(Some(OMX_DISPLAYRECTTYPE {
-            x_offset: x,
-            y_offset: y,
-            width: w,
-            height: h,
-        }))
-    }
-
-    pub fn render_image(
+    fn render_image(
         &mut self,
         image: &DisplayImage,
         content_mode: ContentMode,
+        transition: Transition,
         timeout: i32,
     ) -> Result<(), PipelineError> {
+        // video_render leaves the bars to the layers behind it, so draw them on the CPU
+        let flattened;
+        let (image, content_mode) = match image.background() {
+            Background::None => (image, content_mode),
+            _ => {
+                flattened = flatten(image, content_mode, self.viewport());
+                (&flattened, ContentMode::ScaleToFill)
+            }
+        };
+        let rect = self.image_rect(content_mode, image);
+        let half = Transition {
+            duration: transition.duration / 2,
+            ..transition
+        };
+
+        if self.shown && !transition.is_none() {
+            self.animate(half, self.rect, false)?;
+        }
+
+        let rotation = Orientation::new(self.rotation, false);
+        self.transform = display_transform(image.orientation().then(rotation));
         self.setup()?;
         self.prepare_image(image)?;
-        self.set_image_scale(content_mode, image)?;
+        let (start, alpha) = match transition.is_none() {
+            true => (rect, 0xff),
+            false => transition_region(transition, rect, self.viewport().0, 0f32, true),
+        };
+        self.set_image_config(Some(start), alpha)?;
         omx::empty_this_buffer(self.resize.handle(), self.buffer_header as *mut _)?;
 
         ilclient::wait_for_event(
@@ -270,6 +430,74 @@ impl Pipeline {
 
         self.cleanup_image()?;
 
+        if !transition.is_none() {
+            self.animate(half, rect, true)?;
+        }
+        self.rect = rect;
+        self.shown = true;
+
+        Ok(())
+    }
+
+    fn render_region(
+        &mut self,
+        name: &str,
+        region: Region,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let pipeline = match self.regions.remove(name) {
+            Some(pipeline) => pipeline.with_region(region),
+            None => {
+                let mut pipeline = self.region_pipeline(region);
+                pipeline.init()?;
+                pipeline
+            }
+        };
+        let pipeline = self.regions.entry(name.to_string()).or_insert(pipeline);
+        pipeline.render_image(image, content_mode, Transition::default(), timeout)
+    }
+
+    fn remove_region(&mut self, name: &str) -> Result<(), PipelineError> {
+        match self.regions.remove(name) {
+            Some(mut pipeline) => pipeline.destroy(),
+            None => Ok(()),
+        }
+    }
+
+    fn power_on(&mut self) -> Result<(), PipelineError> {
+        tv::hdmi_power_on_preferred();
         Ok(())
     }
+
+    fn power_off(&mut self) -> Result<(), PipelineError> {
+        tv::power_off();
+        Ok(())
+    }
+
+    fn display_info(&mut self) -> Result<DisplayInfo, PipelineError> {
+        tv::get_display_state()
+    }
+
+    fn screenshot(&mut self) -> Result<RgbaImage, PipelineError> {
+        let (width, height) = self.viewport;
+        let buffer = dispmanx::snapshot(width, height)?;
+        let snapshot = RgbaImage::from_raw(width, height, buffer)
+            .ok_or(PipelineError::Assertion(Operation::SnapshotFailed))?;
+        // The snapshot is of the panel, so undo the rotation
+        Ok(rotate_frame(&snapshot, (360 - self.rotation) % 360).into_owned())
+    }
+
+    // video_render holds a single picture, so it can fade through black but not crossfade
+    fn supports_transition(&self, kind: TransitionKind) -> bool {
+        kind != TransitionKind::Crossfade
+    }
+
+    fn viewport(&self) -> (u32, u32) {
+        match self.region {
+            Some(region) => region.size(),
+            None => self.screen(),
+        }
+    }
 }
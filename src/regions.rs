@@ -0,0 +1,106 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use gotham_derive::*;
+
+use crate::backend::{Backend, RENDER_TIMEOUT};
+use crate::display::{image::*, region::*};
+use crate::error::PipelineError;
+
+struct Entry {
+    region: Region,
+    // Shared, so it can be rendered without holding the regions lock
+    image: Option<(Arc<DisplayImage>, ContentMode)>,
+}
+
+impl Entry {
+    fn info(&self, name: &str) -> RegionInfo {
+        RegionInfo {
+            name: name.to_string(),
+            region: self.region,
+            image: self.image.as_ref().map(|(image, _)| image.info()),
+            content_mode: self.image.as_ref().map(|(_, content_mode)| *content_mode),
+        }
+    }
+}
+
+/// Named regions showing images on top of the main one.
+#[derive(Clone, StateData)]
+pub struct Regions {
+    regions: Arc<Mutex<BTreeMap<String, Entry>>>,
+    backend: Backend,
+}
+
+impl Regions {
+    pub fn new(backend: Backend) -> Regions {
+        Regions {
+            regions: Arc::default(),
+            backend,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Entry>> {
+        self.regions.lock().unwrap()
+    }
+
+    pub fn list(&self) -> Vec<RegionInfo> {
+        self.lock()
+            .iter()
+            .map(|(name, entry)| entry.info(name))
+            .collect()
+    }
+
+    /// Creates or moves a region, redrawing the image it shows.
+    pub fn set(&self, name: &str, region: Region) -> Result<RegionInfo, PipelineError> {
+        let image = {
+            let mut regions = self.lock();
+            let entry = regions.entry(name.to_string()).or_insert(Entry {
+                region,
+                image: None,
+            });
+            entry.region = region;
+            entry.image.clone()
+        };
+        if let Some((image, content_mode)) = &image {
+            self.backend.lock().render_region(
+                name,
+                region,
+                image,
+                *content_mode,
+                RENDER_TIMEOUT,
+            )?;
+        }
+        Ok(Entry { region, image }.info(name))
+    }
+
+    pub fn show(
+        &self,
+        name: &str,
+        image: DisplayImage,
+        content_mode: ContentMode,
+    ) -> Option<Result<RegionInfo, PipelineError>> {
+        let region = self.lock().get(name)?.region;
+        let result =
+            self.backend
+                .lock()
+                .render_region(name, region, &image, content_mode, RENDER_TIMEOUT);
+        Some(result.map(|()| {
+            let image = Some((Arc::new(image), content_mode));
+            // Unless the region was removed while rendering
+            if let Some(entry) = self.lock().get_mut(name) {
+                entry.image = image.clone();
+            }
+            Entry { region, image }.info(name)
+        }))
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Result<RegionInfo, PipelineError>> {
+        let entry = self.lock().remove(name)?;
+        let result = self.backend.lock().remove_region(name);
+        Some(result.map(|()| entry.info(name)))
+    }
+}
@@ -0,0 +1,98 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Datelike, Timelike};
+use gotham_derive::*;
+
+use crate::backend::Backend;
+use crate::display::power::*;
+use crate::wakeup::Wakeup;
+
+const SCHEDULE_FILE: &str = "schedule.json";
+
+/// Turns the display on and off following a weekly schedule in local time.
+#[derive(Clone, StateData)]
+pub struct Scheduler {
+    schedule: Arc<Mutex<PowerSchedule>>,
+    notify: Wakeup,
+    dir: PathBuf,
+    backend: Backend,
+}
+
+impl Scheduler {
+    pub fn new(backend: Backend, dir: PathBuf) -> Scheduler {
+        Scheduler {
+            schedule: Arc::default(),
+            notify: Wakeup::default(),
+            dir,
+            backend,
+        }
+    }
+
+    pub fn load(&self) -> io::Result<()> {
+        let schedule = match fs::read(self.dir.join(SCHEDULE_FILE)) {
+            Ok(schedule) => schedule,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        *self.schedule.lock().unwrap() = serde_json::from_slice(&schedule)?;
+        Ok(())
+    }
+
+    pub fn schedule(&self) -> PowerSchedule {
+        self.schedule.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, schedule: PowerSchedule) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let (path, tmp) = (
+            self.dir.join(SCHEDULE_FILE),
+            self.dir.join(format!("{}.tmp", SCHEDULE_FILE)),
+        );
+        fs::write(&tmp, serde_json::to_vec(&schedule)?)?;
+        fs::rename(tmp, path)?;
+
+        *self.schedule.lock().unwrap() = schedule;
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Changes the power only when the schedule switches, so manual changes last until then.
+    pub async fn run(self) {
+        let mut applied = None;
+        loop {
+            let now = chrono::Local::now();
+            let day = Day::from_index(now.weekday().num_days_from_monday());
+            let time = TimeOfDay::new(now.hour(), now.minute()).expect("valid time of day");
+            let power = self.schedule.lock().unwrap().power(day, time);
+
+            if power.is_some() && power != applied {
+                // The backend may be busy rendering, so wait for its lock off the runtime
+                let backend = self.backend.clone();
+                let result = tokio::task::spawn_blocking(move || match power {
+                    Some(true) => backend.power_on(),
+                    _ => backend.power_off(),
+                })
+                .await;
+                match result {
+                    Ok(Ok(())) => applied = power,
+                    Ok(Err(err)) => log::error!("Failed to change display power: {:?}", err),
+                    Err(err) => log::error!("Failed to change display power: {:?}", err),
+                }
+            }
+
+            let wait = 60 - now.second() as u64;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(wait)) => {}
+                _ = self.notify.notified() => applied = None,
+            }
+        }
+    }
+}
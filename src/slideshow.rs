@@ -0,0 +1,354 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use gotham::hyper::body::Bytes;
+use gotham_derive::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use crate::api::load_image;
+use crate::backend::{Backend, RENDER_TIMEOUT};
+use crate::display::{image::*, transition::*};
+use crate::error::ImageError;
+use crate::store::ImageStore;
+use crate::wakeup::Wakeup;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Slide {
+    pub id: u32,
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+    pub content_mode: ContentMode,
+    pub transition: Transition,
+    #[serde(flatten)]
+    pub layout: Layout,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(skip_serializing)]
+    body: Bytes,
+    #[serde(skip_serializing)]
+    format: Option<String>,
+}
+
+impl Slide {
+    fn body(&self) -> std::io::Result<Bytes> {
+        match &self.path {
+            Some(path) => std::fs::read(path).map(Bytes::from),
+            None => Ok(self.body.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Playlist {
+    pub slides: Vec<Slide>,
+    pub current: Option<u32>,
+    pub interval: u64,
+    pub playing: bool,
+    pub shuffle: bool,
+    #[serde(skip_serializing)]
+    next_id: u32,
+}
+
+impl Playlist {
+    fn position(&self, id: u32) -> Option<usize> {
+        self.slides.iter().position(|slide| slide.id == id)
+    }
+
+    fn position_of_path(&self, path: &Path) -> Option<usize> {
+        self.slides
+            .iter()
+            .position(|slide| slide.path.as_deref() == Some(path))
+    }
+
+    fn step(&mut self, offset: isize) -> Option<Slide> {
+        if self.slides.is_empty() {
+            self.current = None;
+            return None;
+        }
+
+        let len = self.slides.len() as isize;
+        let index = match self.current.and_then(|id| self.position(id)) {
+            Some(index) => (index as isize + offset).rem_euclid(len),
+            None => 0,
+        };
+        let slide = self.slides[index as usize].clone();
+        self.current = Some(slide.id);
+        Some(slide)
+    }
+}
+
+#[derive(Clone, StateData)]
+pub struct Slideshow {
+    playlist: Arc<Mutex<Playlist>>,
+    notify: Wakeup,
+    backend: Backend,
+    store: ImageStore,
+}
+
+impl Slideshow {
+    pub fn new(backend: Backend, store: ImageStore, interval: u64) -> Slideshow {
+        Slideshow {
+            playlist: Arc::new(Mutex::new(Playlist {
+                interval,
+                ..Default::default()
+            })),
+            notify: Wakeup::default(),
+            backend,
+            store,
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Playlist> {
+        self.playlist.lock().unwrap()
+    }
+
+    pub fn add(
+        &self,
+        body: Bytes,
+        format: Option<String>,
+        content_mode: ContentMode,
+        transition: Transition,
+        layout: Layout,
+    ) -> Result<Slide, ImageError> {
+        if !self.backend.supports_transition(transition) {
+            return Err(transition.unsupported_error());
+        }
+        let image = load_image(
+            body.clone(),
+            format.as_deref(),
+            self.backend.max_image_size(),
+        )?;
+
+        let mut playlist = self.lock();
+        let slide = Slide {
+            id: playlist.next_id,
+            width: image.width(),
+            height: image.height(),
+            size: body.len(),
+            content_mode,
+            transition,
+            layout,
+            path: None,
+            body,
+            format,
+        };
+        playlist.next_id += 1;
+        playlist.slides.push(slide.clone());
+        Ok(slide)
+    }
+
+    pub fn add_file(
+        &self,
+        path: PathBuf,
+        content_mode: ContentMode,
+        transition: Transition,
+    ) -> Option<Slide> {
+        let (width, height) = probe_image(&path)?;
+        let size = std::fs::metadata(&path).ok()?.len() as usize;
+
+        let mut playlist = self.lock();
+        if playlist.position_of_path(&path).is_some() {
+            return None;
+        }
+
+        let index = match playlist.shuffle {
+            true => rand::thread_rng().gen_range(0..=playlist.slides.len()),
+            false => playlist
+                .slides
+                .iter()
+                .position(|slide| matches!(&slide.path, Some(p) if p > &path))
+                .unwrap_or_else(|| playlist.slides.len()),
+        };
+        let slide = Slide {
+            id: playlist.next_id,
+            width,
+            height,
+            size,
+            content_mode,
+            transition,
+            layout: Layout::default(),
+            path: Some(path),
+            body: Bytes::new(),
+            format: None,
+        };
+        playlist.next_id += 1;
+        playlist.slides.insert(index, slide.clone());
+        Some(slide)
+    }
+
+    pub fn remove(&self, id: u32) -> Option<Slide> {
+        let mut playlist = self.lock();
+        let index = playlist.position(id)?;
+        Some(playlist.slides.remove(index))
+    }
+
+    pub fn remove_file(&self, path: &Path) -> Option<Slide> {
+        let mut playlist = self.lock();
+        let index = playlist.position_of_path(path)?;
+        Some(playlist.slides.remove(index))
+    }
+
+    pub fn set_shuffle(&self, shuffle: bool) {
+        let mut playlist = self.lock();
+        playlist.shuffle = shuffle;
+        if shuffle {
+            playlist.slides.shuffle(&mut rand::thread_rng());
+        }
+    }
+
+    pub fn move_to(&self, id: u32, index: usize) -> Option<()> {
+        let mut playlist = self.lock();
+        let from = playlist.position(id)?;
+        let slide = playlist.slides.remove(from);
+        let index = index.min(playlist.slides.len());
+        playlist.slides.insert(index, slide);
+        Some(())
+    }
+
+    pub fn set_interval(&self, interval: u64) {
+        self.lock().interval = interval;
+        self.notify.notify_one();
+    }
+
+    pub fn play(&self) {
+        let shown = {
+            let mut playlist = self.lock();
+            playlist.playing = true;
+            playlist.current.is_some()
+        };
+        if shown {
+            self.notify.notify_one();
+        } else {
+            self.show(0);
+        }
+    }
+
+    pub fn pause(&self) {
+        self.lock().playing = false;
+        self.notify.notify_one();
+    }
+
+    pub fn next(&self) {
+        self.show(1);
+    }
+
+    pub fn previous(&self) {
+        self.show(-1);
+    }
+
+    fn show(&self, offset: isize) {
+        let slide = self.lock().step(offset);
+        if let Some(slide) = slide {
+            self.render(&slide);
+        }
+        self.notify.notify_one();
+    }
+
+    fn render(&self, slide: &Slide) {
+        let body = match slide.body() {
+            Ok(body) => body,
+            Err(err) => {
+                log::error!("Failed to read slide {}: {:?}", slide.id, err);
+                return;
+            }
+        };
+        let max_size = self.backend.max_image_size();
+        let mut image = match load_image(body.clone(), slide.format.as_deref(), max_size) {
+            Ok(image) => image,
+            Err(err) => {
+                log::error!("Failed to load slide {}: {:?}", slide.id, err);
+                return;
+            }
+        };
+        slide.layout.apply(&mut image);
+        if let Err(err) =
+            self.backend
+                .render(&image, slide.content_mode, slide.transition, RENDER_TIMEOUT)
+        {
+            log::error!("Failed to render slide {}: {:?}", slide.id, err);
+            return;
+        }
+        // Keep /image/current up to date without rewriting the saved image every slide
+        self.store.set_current(&body, &image, slide.content_mode);
+    }
+
+    pub async fn run(self) {
+        loop {
+            let interval = {
+                let playlist = self.lock();
+                match playlist.playing {
+                    true => Some(Duration::from_secs(playlist.interval)),
+                    false => None,
+                }
+            };
+
+            match interval {
+                Some(interval) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {
+                            // Transitions sleep between frames, so keep them off the runtime
+                            let slideshow = self.clone();
+                            let _ = tokio::task::spawn_blocking(move || slideshow.show(1)).await;
+                        }
+                        _ = self.notify.notified() => {}
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+}
+
+fn probe_image(path: &Path) -> Option<(u32, u32)> {
+    use image::io::Reader as ImageReader;
+
+    let image = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    image.format()?;
+    image.into_dimensions().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::*;
+    use image::{ImageOutputFormat, RgbaImage};
+    use tempfile::TempDir;
+
+    fn png(width: u32, height: u32) -> Bytes {
+        let mut body = std::io::Cursor::new(Vec::new());
+        RgbaImage::new(width, height)
+            .write_to(&mut body, ImageOutputFormat::Png)
+            .unwrap();
+        body.into_inner().into()
+    }
+
+    #[test]
+    fn slides_are_not_saved() {
+        let dir = TempDir::new().unwrap();
+        let mock = MockBackend::new(64, 64);
+        let store = ImageStore::new(Some(dir.path().to_path_buf()));
+        let slideshow = Slideshow::new(Backend::new(mock.clone()), store.clone(), 10);
+        slideshow
+            .add(
+                png(8, 4),
+                None,
+                ContentMode::None,
+                Transition::default(),
+                Layout::default(),
+            )
+            .unwrap();
+
+        slideshow.next();
+        assert_eq!(mock.calls().len(), 1);
+        assert_eq!(store.current().map(|current| current.image.width), Some(8));
+        assert!(!dir.path().join("current.json").exists());
+        assert!(!dir.path().join("current.img").exists());
+    }
+}
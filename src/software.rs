@@ -0,0 +1,279 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::backend::DisplayBackend;
+use crate::display::{frame::*, image::*, power::*, region::*, transition::*};
+use crate::error::{Operation, PipelineError};
+use image::{ImageFormat, RgbaImage};
+
+#[derive(Debug, Default, Clone)]
+pub struct Pipeline {
+    viewport: (u32, u32),
+    rotation: u32,
+    output: Option<PathBuf>,
+    frame: Option<RgbaImage>,
+    base: Option<RgbaImage>,
+    regions: BTreeMap<String, (Region, RgbaImage)>,
+    power: bool,
+}
+
+impl Pipeline {
+    pub fn new(width: u32, height: u32) -> Pipeline {
+        Pipeline {
+            viewport: (width, height),
+            power: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_rotation(&mut self, rotation: u32) {
+        self.rotation = rotation;
+    }
+
+    pub fn set_output(&mut self, output: Option<PathBuf>) {
+        self.output = output;
+    }
+
+    fn with_regions(&self, base: &RgbaImage) -> RgbaImage {
+        let mut frame = base.clone();
+        overlay_regions(&mut frame, self.regions.values());
+        frame
+    }
+
+    fn write_output(&self, frame: &RgbaImage) -> Result<(), PipelineError> {
+        if let Some(output) = &self.output {
+            rotate_frame(frame, self.rotation)
+                .save_with_format(output, ImageFormat::Png)
+                .map_err(|e| PipelineError::ImageError(Operation::WriteFrameFailed, e))?;
+        }
+        Ok(())
+    }
+
+    fn refresh(&mut self) -> Result<(), PipelineError> {
+        let frame = match &self.base {
+            Some(base) => self.with_regions(base),
+            None => self.with_regions(&black_frame(self.viewport())),
+        };
+        self.frame = Some(frame.clone());
+        self.write_output(&frame)
+    }
+}
+
+impl DisplayBackend for Pipeline {
+    fn init(&mut self) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    fn render_image(
+        &mut self,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        _transition: Transition,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let viewport = effective_viewport(self.viewport, self.rotation);
+        let base = composite(image, content_mode, viewport);
+        let frame = self.with_regions(&base);
+        self.base = Some(base);
+
+        self.frame = Some(frame.clone());
+        self.write_output(&frame)
+    }
+
+    fn render_region(
+        &mut self,
+        name: &str,
+        region: Region,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+        _timeout: i32,
+    ) -> Result<(), PipelineError> {
+        let content = composite(image, content_mode, region.size());
+        self.regions.insert(name.to_string(), (region, content));
+        self.refresh()
+    }
+
+    fn remove_region(&mut self, name: &str) -> Result<(), PipelineError> {
+        if self.regions.remove(name).is_some() {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    fn power_on(&mut self) -> Result<(), PipelineError> {
+        self.power = true;
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), PipelineError> {
+        self.power = false;
+        Ok(())
+    }
+
+    fn display_info(&mut self) -> Result<DisplayInfo, PipelineError> {
+        let (width, height) = self.viewport;
+        Ok(DisplayInfo {
+            attached: true,
+            power: self.power,
+            width,
+            height,
+            ..Default::default()
+        })
+    }
+
+    fn screenshot(&mut self) -> Result<RgbaImage, PipelineError> {
+        Ok(match self.frame() {
+            Some(frame) => frame,
+            None => black_frame(self.viewport()),
+        })
+    }
+
+    fn viewport(&self) -> (u32, u32) {
+        effective_viewport(self.viewport, self.rotation)
+    }
+
+    fn frame(&self) -> Option<RgbaImage> {
+        self.frame.clone()
+    }
+
+    fn compose(&self, image: &DisplayImage, content_mode: ContentMode) -> RgbaImage {
+        self.with_regions(&composite(image, content_mode, self.viewport()))
+    }
+
+    fn show_frame(&mut self, frame: &RgbaImage) -> Result<(), PipelineError> {
+        self.frame = Some(frame.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: Rgba<u8> = Rgba([0xff, 0, 0, 0xff]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 0xff, 0xff]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 0xff]);
+
+    // 4x2 image, red on the left half and blue on the right
+    fn render(content_mode: ContentMode) -> RgbaImage {
+        let img = RgbaImage::from_fn(4, 2, |x, _| if x < 2 { RED } else { BLUE });
+        let image = DisplayImage::new(img, 0, ImageFormat::Png);
+        let mut pipeline = Pipeline::new(8, 8);
+        pipeline
+            .render_image(&image, content_mode, Transition::default(), 0)
+            .unwrap();
+        pipeline.screenshot().unwrap()
+    }
+
+    #[test]
+    fn none_centers_image_at_native_size() {
+        let frame = render(ContentMode::None);
+        assert_eq!(frame.dimensions(), (8, 8));
+        assert_eq!(*frame.get_pixel(2, 3), RED);
+        assert_eq!(*frame.get_pixel(5, 4), BLUE);
+        assert_eq!(*frame.get_pixel(1, 3), BLACK);
+        assert_eq!(*frame.get_pixel(6, 3), BLACK);
+        assert_eq!(*frame.get_pixel(2, 2), BLACK);
+        assert_eq!(*frame.get_pixel(2, 5), BLACK);
+    }
+
+    #[test]
+    fn scale_to_fill_stretches_image() {
+        let frame = render(ContentMode::ScaleToFill);
+        assert_eq!(*frame.get_pixel(0, 0), RED);
+        assert_eq!(*frame.get_pixel(2, 7), RED);
+        assert_eq!(*frame.get_pixel(5, 0), BLUE);
+        assert_eq!(*frame.get_pixel(7, 7), BLUE);
+    }
+
+    #[test]
+    fn aspect_fit_letterboxes_image() {
+        let frame = render(ContentMode::Aspect(AspectMode::Fit));
+        assert_eq!(*frame.get_pixel(0, 1), BLACK);
+        assert_eq!(*frame.get_pixel(0, 2), RED);
+        assert_eq!(*frame.get_pixel(7, 5), BLUE);
+        assert_eq!(*frame.get_pixel(7, 6), BLACK);
+    }
+
+    #[test]
+    fn aspect_fill_crops_image() {
+        let frame = render(ContentMode::Aspect(AspectMode::Fill));
+        assert_eq!(*frame.get_pixel(1, 0), RED);
+        assert_eq!(*frame.get_pixel(1, 7), RED);
+        assert_eq!(*frame.get_pixel(6, 0), BLUE);
+        assert_eq!(*frame.get_pixel(6, 7), BLUE);
+    }
+
+    #[test]
+    fn transition_does_not_hold_backend_lock() {
+        use crate::backend::Backend;
+        use std::time::{Duration, Instant};
+
+        let solid =
+            |color| DisplayImage::new(RgbaImage::from_pixel(8, 8, color), 0, ImageFormat::Png);
+        let backend = Backend::new(Pipeline::new(8, 8));
+        backend
+            .render(&solid(RED), ContentMode::None, Transition::default(), 0)
+            .unwrap();
+
+        let fading = backend.clone();
+        let fade = std::thread::spawn(move || {
+            let transition = Transition::from_str("crossfade", Some(500));
+            fading.render(&solid(BLUE), ContentMode::None, transition, 0)
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        let mid = backend.lock().screenshot().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_ne!(*mid.get_pixel(0, 0), RED);
+        assert_ne!(*mid.get_pixel(0, 0), BLUE);
+
+        fade.join().unwrap().unwrap();
+        assert_eq!(*backend.lock().screenshot().unwrap().get_pixel(0, 0), BLUE);
+    }
+
+    #[test]
+    fn rotation_rotates_output() {
+        let output = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        let img = RgbaImage::from_fn(4, 2, |x, _| if x < 2 { RED } else { BLUE });
+        let image = DisplayImage::new(img, 0, ImageFormat::Png);
+        let mut pipeline = Pipeline::new(2, 4);
+        pipeline.set_rotation(90);
+        pipeline.set_output(Some(output.path().to_path_buf()));
+        assert_eq!(pipeline.viewport(), (4, 2));
+        pipeline
+            .render_image(&image, ContentMode::None, Transition::default(), 0)
+            .unwrap();
+
+        let frame = image::open(output.path()).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (2, 4));
+        assert_eq!(*frame.get_pixel(0, 0), RED);
+        assert_eq!(*frame.get_pixel(1, 3), BLUE);
+    }
+
+    #[test]
+    fn screenshot_is_in_viewport_orientation() {
+        let img = RgbaImage::from_fn(4, 2, |x, _| if x < 2 { RED } else { BLUE });
+        let image = DisplayImage::new(img, 0, ImageFormat::Png);
+        let mut pipeline = Pipeline::new(2, 4);
+        pipeline.set_rotation(90);
+        assert_eq!(pipeline.screenshot().unwrap().dimensions(), (4, 2));
+
+        pipeline
+            .render_image(&image, ContentMode::None, Transition::default(), 0)
+            .unwrap();
+        let frame = pipeline.screenshot().unwrap();
+        assert_eq!(frame.dimensions(), (4, 2));
+        assert_eq!(*frame.get_pixel(0, 0), RED);
+        assert_eq!(*frame.get_pixel(3, 1), BLUE);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gotham::hyper::body::Bytes;
+use gotham_derive::*;
+use serde::{Deserialize, Serialize};
+
+use crate::display::{background::*, image::*, orientation::*, rect::*};
+
+const IMAGE_FILE: &str = "current.img";
+const META_FILE: &str = "current.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredMeta {
+    format: Option<String>,
+    content_mode: String,
+    #[serde(default)]
+    orientation: Orientation,
+    #[serde(default)]
+    background: Background,
+    #[serde(default)]
+    focus: Focus,
+    #[serde(default)]
+    crop: Option<CropRect>,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct StoredImage {
+    pub body: Bytes,
+    pub format: Option<String>,
+    pub content_mode: ContentMode,
+    pub orientation: Orientation,
+    pub background: Background,
+    pub focus: Focus,
+    pub crop: Option<CropRect>,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CurrentImage {
+    pub body: Bytes,
+    pub image: ImageInfo,
+    pub content_mode: ContentMode,
+    pub shown_at: u64,
+}
+
+#[derive(Debug, Default, Clone, StateData)]
+pub struct ImageStore {
+    dir: Option<PathBuf>,
+    current: Arc<Mutex<Option<CurrentImage>>>,
+}
+
+impl ImageStore {
+    pub fn new(dir: Option<PathBuf>) -> ImageStore {
+        ImageStore {
+            dir,
+            ..Default::default()
+        }
+    }
+
+    pub fn current(&self) -> Option<CurrentImage> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn set_current(&self, body: &Bytes, image: &DisplayImage, content_mode: ContentMode) {
+        let shown_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        *self.current.lock().unwrap() = Some(CurrentImage {
+            body: body.clone(),
+            image: image.info(),
+            content_mode,
+            shown_at,
+        });
+    }
+
+    pub fn save(
+        &self,
+        body: &Bytes,
+        format: Option<&str>,
+        image: &DisplayImage,
+        content_mode: ContentMode,
+    ) -> io::Result<()> {
+        self.set_current(body, image, content_mode);
+
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let meta = StoredMeta {
+            format: format.map(String::from),
+            content_mode: serde_json::to_value(content_mode)?
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            orientation: image.orientation(),
+            background: image.background(),
+            focus: image.focus(),
+            crop: image.crop(),
+            title: image.caption().title.clone(),
+        };
+
+        fs::create_dir_all(dir)?;
+        let (image, image_tmp) = (
+            dir.join(IMAGE_FILE),
+            dir.join(format!("{}.tmp", IMAGE_FILE)),
+        );
+        let (meta_path, meta_tmp) = (dir.join(META_FILE), dir.join(format!("{}.tmp", META_FILE)));
+        fs::write(&image_tmp, body)?;
+        fs::write(&meta_tmp, serde_json::to_vec(&meta)?)?;
+        fs::rename(image_tmp, image)?;
+        fs::rename(meta_tmp, meta_path)
+    }
+
+    pub fn load(&self) -> io::Result<Option<StoredImage>> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        let meta = match fs::read(dir.join(META_FILE)) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let meta: StoredMeta = serde_json::from_slice(&meta)?;
+        let body = fs::read(dir.join(IMAGE_FILE))?;
+
+        Ok(Some(StoredImage {
+            body: Bytes::from(body),
+            format: meta.format,
+            content_mode: ContentMode::from_str(&meta.content_mode),
+            orientation: meta.orientation,
+            background: meta.background,
+            focus: meta.focus,
+            crop: meta.crop,
+            title: meta.title,
+        }))
+    }
+}
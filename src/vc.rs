@@ -8,6 +8,7 @@ SPDX-License-Identifier: BSD-3-Clause
 #![allow(dead_code)]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+use crate::display::power::DisplayInfo;
 use crate::error::{Operation, PipelineError};
 
 use std::ffi::CString;
@@ -267,6 +268,56 @@ pub mod omx {
     }
 }
 
+pub mod dispmanx {
+    use super::*;
+
+    pub fn snapshot(width: u32, height: u32) -> Result<Vec<u8>, PipelineError> {
+        let pitch = width * 4;
+        let mut buffer = vec![0u8; (pitch * height) as usize];
+        unsafe {
+            let display = vc_dispmanx_display_open(0);
+            if display == 0 {
+                return Err(PipelineError::Assertion(Operation::SnapshotFailed));
+            }
+            let mut image_handle = 0u32;
+            let resource = vc_dispmanx_resource_create(
+                VC_IMAGE_TYPE_T_VC_IMAGE_RGBA32,
+                width,
+                height,
+                &mut image_handle,
+            );
+            if resource == 0 {
+                vc_dispmanx_display_close(display);
+                return Err(PipelineError::Assertion(Operation::SnapshotFailed));
+            }
+
+            let mut rect: VC_RECT_T = std::mem::zeroed();
+            vc_dispmanx_rect_set(&mut rect, 0, 0, width, height);
+            let result = match vc_dispmanx_snapshot(
+                display,
+                resource,
+                DISPMANX_TRANSFORM_T_DISPMANX_NO_ROTATE,
+            ) {
+                0 => vc_dispmanx_resource_read_data(
+                    resource,
+                    &rect,
+                    buffer.as_mut_ptr() as *mut _,
+                    pitch,
+                ),
+                err => err,
+            };
+
+            vc_dispmanx_resource_delete(resource);
+            vc_dispmanx_display_close(display);
+
+            match result {
+                0 => Ok(buffer),
+                _ => Err(PipelineError::Assertion(Operation::SnapshotFailed)),
+            }
+        }
+    }
+}
+
 pub mod tv {
     use super::*;
 
@@ -277,4 +328,56 @@ pub mod tv {
     pub fn power_off() {
         unsafe { tv_power_off() };
     }
+
+    pub fn get_display_state() -> Result<DisplayInfo, PipelineError> {
+        let mut tvstate: TV_DISPLAY_STATE_T = unsafe { std::mem::zeroed() };
+        if unsafe { tv_get_display_state(&mut tvstate) } != 0 {
+            return Err(PipelineError::Assertion(Operation::DisplayStateFailed));
+        }
+
+        let state = tvstate.state;
+        let attached = state
+            & (VC_HDMI_NOTIFY_T_VC_HDMI_ATTACHED
+                | VC_HDMI_NOTIFY_T_VC_HDMI_DVI
+                | VC_HDMI_NOTIFY_T_VC_HDMI_HDMI)
+            != 0
+            || state & VC_SDTV_NOTIFY_T_VC_SDTV_ATTACHED != 0;
+        let mut info = DisplayInfo {
+            attached,
+            ..Default::default()
+        };
+
+        // Same interpretation as `tvservice --status`
+        if state & (VC_HDMI_NOTIFY_T_VC_HDMI_HDMI | VC_HDMI_NOTIFY_T_VC_HDMI_DVI) != 0 {
+            let hdmi = unsafe { tvstate.display.hdmi };
+            let group = match hdmi.group {
+                HDMI_RES_GROUP_T_HDMI_RES_GROUP_CEA => "CEA",
+                HDMI_RES_GROUP_T_HDMI_RES_GROUP_DMT => "DMT",
+                _ => "unknown",
+            };
+            info.power = true;
+            info.output = Some(match state & VC_HDMI_NOTIFY_T_VC_HDMI_HDMI != 0 {
+                true => "hdmi".to_string(),
+                false => "dvi".to_string(),
+            });
+            info.mode = Some(format!("{} {}", group, hdmi.mode));
+            info.width = hdmi.width;
+            info.height = hdmi.height;
+            info.refresh_rate = Some(hdmi.frame_rate as u32);
+            info.interlaced = hdmi.scan_mode != 0;
+        } else if state & (VC_SDTV_NOTIFY_T_VC_SDTV_NTSC | VC_SDTV_NOTIFY_T_VC_SDTV_PAL) != 0 {
+            let sdtv = unsafe { tvstate.display.sdtv };
+            info.power = true;
+            info.output = Some("sdtv".to_string());
+            info.mode = Some(match state & VC_SDTV_NOTIFY_T_VC_SDTV_NTSC != 0 {
+                true => "NTSC".to_string(),
+                false => "PAL".to_string(),
+            });
+            info.width = sdtv.width;
+            info.height = sdtv.height;
+            info.refresh_rate = Some(sdtv.frame_rate as u32);
+            info.interlaced = sdtv.scan_mode != 0;
+        }
+        Ok(info)
+    }
 }
@@ -0,0 +1,23 @@
+/*
+Copyright (c) 2021, Yuki MIZUNO
+SPDX-License-Identifier: BSD-3-Clause
+*/
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+/// Shared `Notify` for waking background loops from request handlers.
+///
+/// gotham state must be RefUnwindSafe, which Notify is not on older tokio.
+#[derive(Clone, Default)]
+pub struct Wakeup(Arc<AssertUnwindSafe<Notify>>);
+
+impl Deref for Wakeup {
+    type Target = Notify;
+
+    fn deref(&self) -> &Notify {
+        &self.0
+    }
+}
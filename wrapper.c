@@ -78,3 +78,9 @@ void tv_power_off()
   vchi_disconnect(vchi_instance);
   vcos_deinit();
 }
+
+int tv_get_display_state(TV_DISPLAY_STATE_T *tvstate)
+{
+  /* Uses the TV service connection opened by bcm_host_init */
+  return vc_tv_get_display_state(tvstate);
+}
@@ -18,3 +18,4 @@ OMX_S32 wOMX_UseBuffer(OMX_HANDLETYPE hComponent, OMX_BUFFERHEADERTYPE **ppBuffe
 OMX_S32 wOMX_FreeBuffer(OMX_HANDLETYPE hComponent, OMX_U32 nPortIndex, OMX_BUFFERHEADERTYPE *pBuffer);
 void tv_hdmi_power_on_preferred();
 void tv_power_off();
+int tv_get_display_state(TV_DISPLAY_STATE_T *tvstate);